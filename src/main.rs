@@ -1,11 +1,23 @@
 use anyhow::Result;
-use clap::{CommandFactory, Parser, Subcommand};
-use rmcp::{ServiceExt, transport::stdio};
-use std::io::IsTerminal;
+use clap::{Args, CommandFactory, Parser, Subcommand};
+use rmcp::{
+    ServiceExt,
+    transport::{
+        stdio,
+        streamable_http_server::{
+            StreamableHttpServerConfig, StreamableHttpService, session::local::LocalSessionManager,
+        },
+    },
+};
+use std::{io::IsTerminal, net::SocketAddr, path::PathBuf, sync::Arc};
+use tokio_util::sync::CancellationToken;
 use tracing_subscriber::{self, EnvFilter};
 
 mod server;
-use server::DVBServer;
+use server::{
+    CachingBackend, Clock, ContextFile, DEFAULT_CACHE_SIZE, DVBServer, FixtureBackend, LiveBackend,
+    RecordingBackend, TransitBackend, UserContextStore,
+};
 
 const AFTER_HELP: &str = "\
 MCP Server Information:
@@ -15,6 +27,9 @@ MCP Server Information:
     For MCP Inspector:
       npx @modelcontextprotocol/inspector <path-to-dvb-mcp>
 
+    For streamable HTTP (one session per connected client):
+      dvb-mcp serve --http 127.0.0.1:8080
+
     Note: The 'serve' command is optional - auto-detection handles most cases.";
 
 #[derive(Parser)]
@@ -25,12 +40,91 @@ MCP Server Information:
 struct Cli {
     #[command(subcommand)]
     command: Option<Commands>,
+
+    #[command(flatten)]
+    persistence: PersistenceArgs,
+
+    #[command(flatten)]
+    backend: BackendArgs,
+}
+
+#[derive(Args)]
+struct BackendArgs {
+    /// Save every upstream request and response into DIR
+    #[arg(long, global = true, value_name = "DIR")]
+    record: Option<PathBuf>,
+
+    /// Answer purely from recordings in DIR instead of querying the VVO API
+    #[arg(long, global = true, value_name = "DIR", conflicts_with = "record")]
+    replay: Option<PathBuf>,
+
+    /// Number of upstream responses to keep in memory, 0 disables the cache
+    #[arg(long, global = true, value_name = "N", env = "DVB_MCP_CACHE_SIZE", default_value_t = DEFAULT_CACHE_SIZE)]
+    cache_size: usize,
+}
+
+impl BackendArgs {
+    fn server(self, contexts: UserContextStore) -> Result<DVBServer> {
+        if let Some(dir) = self.replay {
+            tracing::info!("replaying upstream responses from {}", dir.display());
+            let replay = FixtureBackend::from_dir(dir)?;
+            // answers relative to now have to be computed as they were while recording
+            let clock = match replay.recorded_at() {
+                Some(time) => Clock::fixed(time),
+                None => Clock::system(),
+            };
+            return Ok(DVBServer::new(contexts, Arc::new(replay)).with_clock(clock));
+        }
+        Ok(DVBServer::new(contexts, self.backend()?))
+    }
+
+    fn backend(self) -> Result<Arc<dyn TransitBackend>> {
+        // recordings capture what the tools saw, cached answers included
+        let live =
+            Arc::new(CachingBackend::new(Arc::new(LiveBackend)).with_capacity(self.cache_size));
+        if let Some(dir) = self.record {
+            tracing::info!("recording upstream responses to {}", dir.display());
+            return Ok(Arc::new(RecordingBackend::new(live, dir)?));
+        }
+        Ok(live)
+    }
+}
+
+#[derive(Args)]
+struct PersistenceArgs {
+    /// Keep the saved places and route preferences across restarts (not with --http)
+    #[arg(long, global = true, env = "DVB_MCP_PERSIST_CONTEXT")]
+    persist_context: bool,
+
+    /// File to persist the context in, implies --persist-context
+    /// [default: $XDG_DATA_HOME/dvb-mcp/context.json]
+    #[arg(long, global = true, value_name = "PATH", env = "DVB_MCP_CONTEXT_FILE")]
+    context_file: Option<PathBuf>,
+}
+
+impl PersistenceArgs {
+    fn is_enabled(&self) -> bool {
+        self.persist_context || self.context_file.is_some()
+    }
+
+    fn context_store(self) -> Result<UserContextStore> {
+        let path = match self.context_file {
+            Some(path) => path,
+            None if self.persist_context => ContextFile::default_path().ok_or_else(|| {
+                anyhow::anyhow!("cannot determine data directory, please pass --context-file")
+            })?,
+            None => return Ok(UserContextStore::default()),
+        };
+
+        tracing::info!("persisting user context in {}", path.display());
+        Ok(UserContextStore::persistent(ContextFile::new(path)))
+    }
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Start the MCP server (listens on stdin/stdout)
-    Serve,
+    /// Start the MCP server (listens on stdin/stdout unless --http is given)
+    Serve(ServeArgs),
 
     /// List available resources
     #[command(subcommand)]
@@ -40,6 +134,17 @@ enum Commands {
     Version,
 }
 
+#[derive(Args)]
+struct ServeArgs {
+    /// Serve streamable HTTP on this address instead of stdin/stdout
+    #[arg(long, value_name = "ADDR")]
+    http: Option<SocketAddr>,
+
+    /// Additional `Host` header values to accept in HTTP mode (loopback is always allowed)
+    #[arg(long = "allow-host", value_name = "HOST", requires = "http")]
+    allowed_hosts: Vec<String>,
+}
+
 #[derive(Subcommand)]
 enum ListCommands {
     /// List all available tools
@@ -68,7 +173,21 @@ async fn main() -> Result<()> {
             }
             Ok(())
         }
-        Some(Commands::Serve) => start_mcp_server().await,
+        Some(Commands::Serve(ServeArgs {
+            http: Some(addr),
+            allowed_hosts,
+        })) => {
+            // a single context file would be shared by every client connecting over HTTP
+            if cli.persistence.is_enabled() {
+                anyhow::bail!("--persist-context and --context-file cannot be used with --http");
+            }
+            let server = cli.backend.server(cli.persistence.context_store()?)?;
+            start_http_server(server, addr, allowed_hosts).await
+        }
+        Some(Commands::Serve(ServeArgs { http: None, .. })) => {
+            let server = cli.backend.server(cli.persistence.context_store()?)?;
+            start_mcp_server(server).await
+        }
         Some(Commands::Version) => {
             println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
             Ok(())
@@ -82,19 +201,54 @@ async fn main() -> Result<()> {
                 Ok(())
             } else {
                 // stdin is piped - assume MCP client connection
-                start_mcp_server().await
+                let server = cli.backend.server(cli.persistence.context_store()?)?;
+                start_mcp_server(server).await
             }
         }
     }
 }
 
-async fn start_mcp_server() -> Result<()> {
+async fn start_mcp_server(server: DVBServer) -> Result<()> {
     tracing::info!("DVB MCP Server starting");
 
-    let service = DVBServer::default().serve(stdio()).await.inspect_err(|e| {
+    let service = server.serve(stdio()).await.inspect_err(|e| {
         tracing::error!("serving error: {:?}", e);
     })?;
 
     service.waiting().await?;
     Ok(())
 }
+
+async fn start_http_server(
+    server: DVBServer,
+    addr: SocketAddr,
+    allowed_hosts: Vec<String>,
+) -> Result<()> {
+    let ct = CancellationToken::new();
+
+    let mut config =
+        StreamableHttpServerConfig::default().with_cancellation_token(ct.child_token());
+    config.allowed_hosts.extend(allowed_hosts);
+
+    // every client session gets its own user context
+    let service = StreamableHttpService::new(
+        move || Ok(server.new_session()),
+        LocalSessionManager::default().into(),
+        config,
+    );
+
+    let router = axum::Router::new().nest_service("/mcp", service);
+    let listener = tokio::net::TcpListener::bind(addr).await?;
+    tracing::info!(
+        "DVB MCP Server listening on http://{}/mcp",
+        listener.local_addr()?
+    );
+
+    axum::serve(listener, router)
+        .with_graceful_shutdown(async move {
+            let _ = tokio::signal::ctrl_c().await;
+            ct.cancel();
+        })
+        .await?;
+    Ok(())
+}
@@ -1,4 +1,4 @@
-use anyhow::{Result, anyhow};
+use anyhow::Result;
 use rmcp::{
     ErrorData as McpError,
     handler::server::{
@@ -10,20 +10,55 @@ use rmcp::{
     service::{RequestContext, RoleServer},
     tool, tool_router,
 };
-use tokio::sync::Mutex;
-
-use dvb::{find_stops, point::Point};
 use std::sync::Arc;
 
 use rmcp::schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
 mod args;
+mod backend;
+mod board;
+mod clock;
+mod completion;
+mod context_file;
+mod departure_plan;
+mod disambiguation;
+mod itinerary;
+mod markdown;
 mod osm_links;
+mod progress;
 mod server_handle;
+mod subscriptions;
+mod summary;
+#[cfg(test)]
+mod tests;
 mod usercontext;
 
-use crate::server::{args::DVBPointCoords, osm_links::OsmCoords, usercontext::UserContext};
+use crate::server::{
+    args::{DVBPointCoords, DetailLevel, Wgs84Coords, percent_encode},
+    backend::tracked::TrackedBackend,
+    completion::Completions,
+    departure_plan::{DEFAULT_BUFFER_MINUTES, DeparturePlan},
+    disambiguation::{StopCandidate, StopResolution, resolve_stop},
+    itinerary::{RouteOptions, Waypoint},
+    osm_links::OsmCoords,
+    progress::TripProgress,
+    subscriptions::{Subscriber, Subscriptions},
+    summary::RouteDetails,
+    usercontext::{ContextValue, Session, UserContext},
+};
+
+pub use crate::server::{
+    backend::{
+        LiveBackend, TransitBackend,
+        cache::{CachingBackend, DEFAULT_CAPACITY as DEFAULT_CACHE_SIZE},
+        fixture::FixtureBackend,
+        record::RecordingBackend,
+    },
+    clock::Clock,
+    context_file::ContextFile,
+    usercontext::UserContextStore,
+};
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct OsmLinkResponse {
@@ -31,24 +66,22 @@ pub struct OsmLinkResponse {
 }
 
 /// Simple server with elicitation
+///
+/// Clones belong to the same session, use [`DVBServer::new_session`] to serve another client.
 #[derive(Clone)]
 pub struct DVBServer {
     tool_router: ToolRouter<DVBServer>,
     prompt_router: PromptRouter<DVBServer>,
-    user_origin: Arc<Mutex<Option<String>>>,
-    user_location: Arc<Mutex<Option<String>>>,
-    user_destination: Arc<Mutex<Option<String>>>,
+    session: Arc<Session>,
+    backend: Arc<dyn TransitBackend>,
+    subscriptions: Arc<Subscriber>,
+    completions: Arc<Completions>,
+    clock: Clock,
 }
 
 impl Default for DVBServer {
     fn default() -> Self {
-        Self {
-            tool_router: Self::tool_router(),
-            prompt_router: Self::prompt_router(),
-            user_origin: Arc::new(Mutex::new(None)),
-            user_location: Arc::new(Mutex::new(None)),
-            user_destination: Arc::new(Mutex::new(None)),
-        }
+        Self::new(UserContextStore::default(), Arc::new(LiveBackend))
     }
 }
 
@@ -60,17 +93,56 @@ fn success_json<T: serde::Serialize>(data: &T) -> CallToolResult {
     CallToolResult::success(vec![Content::json(data).unwrap()])
 }
 
+fn success_markdown(text: String) -> CallToolResult {
+    CallToolResult::success(vec![Content::text(text)])
+}
+
 fn error_text<S: Into<String>>(text: S) -> CallToolResult {
     CallToolResult::error(vec![Content::text(text.into())])
 }
 
-async fn lookup_stop_id(query: &str) -> anyhow::Result<String> {
-    let found_origin = find_stops(query).await?;
-    let Point { id, .. } = found_origin
-        .points
-        .first()
-        .ok_or_else(|| anyhow!("empty response"))?;
-    Ok(id.to_owned())
+/// Live data attached to a prompt
+fn embedded(contents: ResourceContents) -> PromptMessage {
+    let resource = RawEmbeddedResource::new(contents).no_annotation();
+    PromptMessage::new(
+        PromptMessageRole::User,
+        PromptMessageContent::Resource { resource },
+    )
+}
+
+/// The place given for the tool argument `field`, either by name or id, or by GPS position in `coords_field`
+///
+/// Positions are turned into VVO `coord:` points. Fails with the tool result to return instead.
+fn place_argument(
+    field: &str,
+    place: Option<String>,
+    coords_field: &str,
+    coords: Option<Wgs84Coords>,
+) -> Result<Option<String>, CallToolResult> {
+    match (place, coords) {
+        (Some(_), Some(_)) => Err(error_text(format!(
+            "give either {field} or {coords_field}, not both"
+        ))),
+        (place, None) => Ok(place),
+        (None, Some(coords)) => osm_links::dvb_point(coords)
+            .map(Some)
+            .map_err(|e| error_text(format!("invalid coordinates for {coords_field}: {e}"))),
+    }
+}
+
+/// `explicit` if given, otherwise `fallback` from the saved context, noting it in `used`
+fn or_from_context(
+    explicit: Option<String>,
+    fallback: Option<ContextValue>,
+    used: &mut Vec<ContextValue>,
+) -> Option<String> {
+    if explicit.is_some() {
+        return explicit;
+    }
+    let fallback = fallback?;
+    let value = fallback.value.clone();
+    used.push(fallback);
+    Some(value)
 }
 
 #[prompt_router]
@@ -78,120 +150,202 @@ impl DVBServer {
     /// Dresden public transport navigation assistant
     #[prompt(
         name = "navigation-assistant",
-        description = "Interactive assistant for navigating Dresden's public transportation system"
+        description = "Interactive assistant for navigating Dresden's public transportation system. Given an origin and destination (or with both saved in the user context), the connections at `time` (default now) are attached."
     )]
-    async fn navigation_assistant(&self) -> Vec<PromptMessage> {
-        vec![
-            PromptMessage::new_text(
-                PromptMessageRole::Assistant,
-                "You are a travel assistant for Dresden's public transportation system (DVB). \
-                 You have access to tools from the local public transportation provider. \
-                 You can help users navigate the city by finding stations, checking departure times, \
-                 and planning routes. Use the available tools to provide a pleasant experience. \
-                 When asked for navigation assistance, first determine the user's origin and destination, \
-                 then use the route planning tools to find the best connections.",
-            ),
+    async fn navigation_assistant(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(args::NavigationPromptArgs {
+            origin,
+            destination,
+            time,
+        }): Parameters<args::NavigationPromptArgs>,
+    ) -> Result<Vec<PromptMessage>, McpError> {
+        let mut messages = vec![PromptMessage::new_text(
+            PromptMessageRole::Assistant,
+            "You are a travel assistant for Dresden's public transportation system (DVB). \
+             You have access to tools from the local public transportation provider. \
+             You can help users navigate the city by finding stations, checking departure times, \
+             and planning routes. Use the available tools to provide a pleasant experience. \
+             When asked for navigation assistance, first determine the user's origin and destination, \
+             then use the route planning tools to find the best connections. \
+             If connections are attached, they were fetched just now: answer from them and \
+             call get_route_details again for other times or updates.",
+        )];
+
+        let saved = self.session.context();
+        let mut from_context = Vec::new();
+        let origin = or_from_context(origin, saved.route_origin(), &mut from_context);
+        let destination =
+            or_from_context(destination, saved.route_destination(), &mut from_context);
+        let (Some(origin), Some(destination)) = (origin, destination) else {
+            messages.extend([
+                PromptMessage::new_text(
+                    PromptMessageRole::User,
+                    "I need help getting around Dresden using public transport.",
+                ),
+                PromptMessage::new_text(
+                    PromptMessageRole::Assistant,
+                    "I'd be happy to help you navigate Dresden's public transportation! \
+                     To get started, I'll need to know where you are and where you'd like to go. \
+                     I can help you find nearby stations, check departure times, and plan your route. \
+                     What's your journey today?",
+                ),
+            ]);
+            return Ok(messages);
+        };
+
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        let origin = self.prompt_stop(&backend, "origin", &origin).await?;
+        let destination = self
+            .prompt_stop(&backend, "destination", &destination)
+            .await?;
+        let time = time.unwrap_or_else(|| self.clock.now());
+        let stops = [&origin, &destination].map(|stop| Waypoint {
+            name: stop.name.clone(),
+            stop_id: stop.stop_id.clone(),
+            dwell_minutes: 0,
+        });
+        let options = RouteOptions {
+            isarrivaltime: false,
+            shorttermchanges: true,
+            mobility: None,
+            standard: None,
+        };
+        let legs = itinerary::plan(&backend, &stops, time, &options)
+            .await
+            .map_err(|error| {
+                McpError::internal_error(format!("failed to fetch connections: {error}"), None)
+            })?;
+        let details = RouteDetails::new(&legs, DetailLevel::Legs, from_context);
+        // routes are no resource of their own, the uri only names this snapshot
+        let uri = format!(
+            "dvb://routes/{}/{}?time={}",
+            origin.stop_id,
+            destination.stop_id,
+            percent_encode(&time.to_rfc3339())
+        );
+
+        messages.extend([
             PromptMessage::new_text(
                 PromptMessageRole::User,
-                "I need help getting around Dresden using public transport.",
+                format!("I want to go from {} to {}.", origin.name, destination.name),
             ),
-            PromptMessage::new_text(
-                PromptMessageRole::Assistant,
-                "I'd be happy to help you navigate Dresden's public transportation! \
-                 To get started, I'll need to know where you are and where you'd like to go. \
-                 I can help you find nearby stations, check departure times, and plan your route. \
-                 What's your journey today?",
-            ),
-        ]
+            embedded(ResourceContents::text(
+                serde_json::to_string_pretty(&details).unwrap(),
+                uri,
+            )),
+        ]);
+        Ok(messages)
     }
 
     /// Real-time departure monitor for Dresden public transport stops
     #[prompt(
         name = "departure-monitor",
-        description = "Real-time departure board for checking when the next vehicles are leaving from a specific station"
+        description = "Real-time departure board for checking when the next vehicles are leaving from a specific station. The current board of `station` (or the saved location) is attached."
     )]
-    async fn departure_monitor(&self) -> Vec<PromptMessage> {
-        vec![
-            PromptMessage::new_text(
-                PromptMessageRole::Assistant,
-                "You are a real-time departure monitor assistant for Dresden's public transportation system (DVB). \
-                 Your primary focus is to quickly provide departure information from specific stations. \
-                 When a user asks about departures, use find_stations to locate the stop, then use monitor_departures to show real-time information. \
-                 Always present departures as a Markdown table with columns: Line, Destination, Departure (in min/time), Platform/Bay, and Delay/Status if available. \
-                 Highlight any delays or disruptions. Be concise and fast—users at a stop need quick answers. \
-                 If the user doesn't specify a station, ask them which stop they're interested in.",
-            ),
+    async fn departure_monitor(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(args::DeparturePromptArgs { station }): Parameters<args::DeparturePromptArgs>,
+    ) -> Result<Vec<PromptMessage>, McpError> {
+        let mut messages = vec![PromptMessage::new_text(
+            PromptMessageRole::Assistant,
+            "You are a real-time departure monitor assistant for Dresden's public transportation system (DVB). \
+             Your primary focus is to quickly provide departure information from specific stations. \
+             When a user asks about departures, use find_stations to locate the stop, then use monitor_departures to show real-time information. \
+             Call monitor_departures with format \"markdown\" and present the table it returns (Line, Destination, Departure, Platform, Status) as it is. \
+             If a departure board is attached, it was fetched just now: answer from it and only call monitor_departures for updates or other stations. \
+             Highlight any delays or disruptions. Be concise and fast—users at a stop need quick answers. \
+             If the user doesn't specify a station, ask them which stop they're interested in.",
+        )];
+
+        let Some(station) = station.or(self.session.context().location) else {
+            return Ok(messages);
+        };
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        let stop = self.prompt_stop(&backend, "station", &station).await?;
+        let board = self
+            .live_contents(&backend, format!("dvb://departures/{}", stop.stop_id))
+            .await?;
+
+        messages.extend([
             PromptMessage::new_text(
                 PromptMessageRole::User,
-                "When is the next tram from Postplatz?",
-            ),
-            PromptMessage::new_text(
-                PromptMessageRole::Assistant,
-                "Let me check the real-time departures at Postplatz for you. Here are the next trams:\n\n\
-| Line | Destination | Departure | Platform | Status |\n\
-|------|-------------|-----------|----------|--------|\n\
-| 1    | Prohlis     | 2 min     | 2        | On time |\n\
-| 2    | Gorbitz     | 5 min     | 1        | +3 min delay |\n\
-| 4    | Weinböhla   | 7 min     | 3        | On time |\n\n\
-If you want to see departures for a different line or direction, just let me know!",
+                format!("When are the next departures from {}?", stop.name),
             ),
-        ]
+            embedded(board),
+        ]);
+        Ok(messages)
     }
 
     /// Trip tracker for following a specific trip in real-time
     #[prompt(
         name = "trip-tracker",
-        description = "Track a specific trip in real-time to see current location, delays, and connection status"
+        description = "Track a specific trip in real-time to see current location, delays, and connection status. Given `trip_id` and `stop_id` the current progress of the trip is attached."
     )]
-    async fn trip_tracker(&self) -> Vec<PromptMessage> {
-        vec![
-            PromptMessage::new_text(
-                PromptMessageRole::Assistant,
-                "You are a trip tracking assistant for Dresden's public transportation system (DVB). \
-                 Your role is to help users track specific trips in real-time using trip IDs.\n\n\
-                 CRITICAL: Trip tracking REQUIRES a trip ID. Trip IDs are obtained from route planning results \
-                 (get_route_details tool). Each connection in a route has a unique trip ID that identifies that \
-                 specific vehicle's journey.\n\n\
-                 WORKFLOW:\n\
-                 1. When user asks to track a trip, identify the trip ID from the previous route planning\n\
-                 2. ALWAYS use the get_trip_details tool with the trip ID to fetch real-time data\n\
-                 3. Store the trip ID in your conversation context for future updates\n\
-                 4. When user asks for updates ('Where is my tram?'), use get_trip_details again with the same trip ID\n\n\
-                 The get_trip_details tool provides:\n\
-                 - Real-time stop sequence and timing\n\
-                 - Current vehicle location (which stops are passed/upcoming)\n\
-                 - Delay information\n\
-                 - Platform/track details\n\n\
-                 Display format:\n\
-                 - Current status (on time or delayed)\n\
-                 - Stops already passed (with checkmarks ✓)\n\
-                 - Next upcoming stop (with arrow →)\n\
-                 - Future stops on the route\n\
-                 - Connection security if they have transfers\n\n\
-                 REMEMBER: Without a trip ID, you cannot track a trip. Always keep the trip ID in context \
-                 throughout the conversation so you can provide updates when asked.",
-            ),
-            PromptMessage::new_text(
-                PromptMessageRole::User,
-                "Track my trip on tram 3 that leaves at 14:12 from Albertplatz",
-            ),
-            PromptMessage::new_text(
-                PromptMessageRole::Assistant,
-                "I'll track your trip on tram 3 departing at 14:12 from Albertplatz. \
-Let me use get_trip_details with the trip ID from your route.\n\n\
-[Using get_trip_details with trip_id: \"voe:11003: :R:j24\"]\n\n\
-**Current Status:** On time\n\n\
-**Trip Progress:**\n\
-✓ Albertplatz (14:12) - Departed\n\
-→ Carolaplatz (14:15) - Next stop, arriving in 2 minutes\n\
-  Pirnaischer Platz (14:17)\n\
-  Hauptbahnhof (14:20)\n\
-  Walpurgisstraße (14:23)\n\
-  Münchner Platz (14:25)\n\n\
-I'm tracking trip ID \"voe:11003: :R:j24\". Just ask me 'Where is my tram?' anytime for an update, \
-and I'll use get_trip_details to fetch the latest real-time information!",
-            ),
-        ]
+    async fn trip_tracker(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(args::TripPromptArgs {
+            trip_id,
+            stop_id,
+            time,
+        }): Parameters<args::TripPromptArgs>,
+    ) -> Result<Vec<PromptMessage>, McpError> {
+        let mut messages = vec![PromptMessage::new_text(
+            PromptMessageRole::Assistant,
+            "You are a trip tracking assistant for Dresden's public transportation system (DVB). \
+             Your role is to help users track specific trips in real-time using trip IDs.\n\n\
+             CRITICAL: Trip tracking REQUIRES a trip ID. Trip IDs are obtained from route planning results \
+             (get_route_details tool). Each connection in a route has a unique trip ID that identifies that \
+             specific vehicle's journey.\n\n\
+             WORKFLOW:\n\
+             1. When user asks to track a trip, identify the trip ID from the previous route planning\n\
+             2. ALWAYS use the get_trip_details tool with the trip ID and format \"markdown\" to fetch real-time data\n\
+             3. Store the trip ID in your conversation context for future updates\n\
+             4. When user asks for updates ('Where is my tram?'), use get_trip_details again with the same trip ID\n\n\
+             If the trip is attached, it was fetched just now: start from it. Its uri can be subscribed \
+             to, to be told when the trip changes.\n\n\
+             The get_trip_details tool provides:\n\
+             - Real-time stop sequence and timing\n\
+             - Current vehicle location (which stops are passed/upcoming)\n\
+             - Delay information\n\
+             - Platform/track details\n\n\
+             Display format (the markdown result already looks like this, show it as it is):\n\
+             - Current status (on time or delayed)\n\
+             - Stops already passed (with checkmarks ✓)\n\
+             - Next upcoming stop (with arrow →)\n\
+             - Future stops on the route\n\
+             - Connection security if they have transfers\n\n\
+             REMEMBER: Without a trip ID, you cannot track a trip. Always keep the trip ID in context \
+             throughout the conversation so you can provide updates when asked.",
+        )];
+
+        let Some(trip_id) = trip_id else {
+            return Ok(messages);
+        };
+        let Some(stop_id) = stop_id else {
+            return Err(McpError::invalid_params(
+                "stop_id is required to track a trip, use the stop the trip was boarded at",
+                Some(serde_json::json!({ "trip_id": trip_id })),
+            ));
+        };
+        let time = time.unwrap_or_else(|| self.clock.now());
+        let uri = format!(
+            "dvb://trips/{}?stop_id={}&time={}",
+            percent_encode(&trip_id),
+            percent_encode(&stop_id),
+            percent_encode(&time.to_rfc3339())
+        );
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        let trip = self.live_contents(&backend, uri).await?;
+
+        messages.extend([
+            PromptMessage::new_text(PromptMessageRole::User, format!("Track my trip {trip_id}.")),
+            embedded(trip),
+        ]);
+        Ok(messages)
     }
 }
 
@@ -202,11 +356,7 @@ impl DVBServer {
         description = "IMPORTANT: Call this at the start of conversations to get user's saved origin, current location, destination, and preferences. Returns all context in one call to avoid redundant questions."
     )]
     async fn get_user_context(&self) -> Result<CallToolResult, McpError> {
-        let origin = self.user_origin.lock().await.clone();
-        let location = self.user_location.lock().await.clone();
-        let destination = self.user_destination.lock().await.clone();
-
-        let context = UserContext::new(origin, location, destination);
+        let context = UserContext::from(self.session.context());
 
         Ok(success_json(&context))
     }
@@ -218,7 +368,7 @@ impl DVBServer {
         &self,
         context: RequestContext<RoleServer>,
     ) -> Result<CallToolResult, McpError> {
-        let current_origin = if let Some(origin) = self.user_origin.lock().await.clone() {
+        let current_origin = if let Some(origin) = self.session.context().origin {
             origin
         } else {
             match context
@@ -230,7 +380,8 @@ impl DVBServer {
             {
                 Ok(Some(user_info)) => {
                     let origin = user_info.origin.clone();
-                    *self.user_origin.lock().await = Some(origin.clone());
+                    self.session
+                        .update_context(|saved| saved.origin = Some(origin.clone()));
                     origin
                 }
                 Ok(None) => "Hauptbahnhof Dresden".to_string(), // Never happen if client checks schema
@@ -248,7 +399,7 @@ impl DVBServer {
         &self,
         context: RequestContext<RoleServer>,
     ) -> Result<CallToolResult, McpError> {
-        let current_location = if let Some(location) = self.user_location.lock().await.clone() {
+        let current_location = if let Some(location) = self.session.context().location {
             location
         } else {
             match context
@@ -258,7 +409,8 @@ impl DVBServer {
             {
                 Ok(Some(user_info)) => {
                     let location = user_info.location.clone();
-                    *self.user_location.lock().await = Some(location.clone());
+                    self.session
+                        .update_context(|saved| saved.location = Some(location.clone()));
                     location
                 }
                 Ok(None) => "Hauptbahnhof Dresden".to_string(), // Never happen if client checks schema
@@ -276,9 +428,7 @@ impl DVBServer {
         &self,
         context: RequestContext<RoleServer>,
     ) -> Result<CallToolResult, McpError> {
-        let current_destination = if let Some(destination) =
-            self.user_destination.lock().await.clone()
-        {
+        let current_destination = if let Some(destination) = self.session.context().destination {
             destination
         } else {
             match context
@@ -288,7 +438,8 @@ impl DVBServer {
             {
                 Ok(Some(dest_info)) => {
                     let destination = dest_info.destination.clone();
-                    *self.user_destination.lock().await = Some(destination.clone());
+                    self.session
+                        .update_context(|saved| saved.destination = Some(destination.clone()));
                     destination
                 }
                 Ok(None) => "Hauptbahnhof Dresden".to_string(),
@@ -306,18 +457,27 @@ impl DVBServer {
         &self,
         Parameters(args::OriginInfo { origin }): Parameters<args::OriginInfo>,
     ) -> Result<CallToolResult, McpError> {
-        *self.user_origin.lock().await = Some(origin.clone());
+        self.session
+            .update_context(|saved| saved.origin = Some(origin.clone()));
         Ok(success_text(format!("Origin set to: {}", origin)))
     }
 
     #[tool(
-        description = "Set the user's current location directly when provided in conversation. Use this when the user tells you where they are right now (e.g., 'I'm currently at Altmarkt'). For interactive prompting, use elicit_location instead."
+        description = "Set the user's current location directly when provided in conversation. Use this when the user tells you where they are right now (e.g., 'I'm currently at Altmarkt'), or pass `coords` with the GPS position of their device. For interactive prompting, use elicit_location instead."
     )]
     async fn set_location(
         &self,
-        Parameters(args::LocationInfo { location }): Parameters<args::LocationInfo>,
+        Parameters(args::SetLocationRequest { location, coords }): Parameters<
+            args::SetLocationRequest,
+        >,
     ) -> Result<CallToolResult, McpError> {
-        *self.user_location.lock().await = Some(location.clone());
+        let location = match place_argument("location", location, "coords", coords) {
+            Ok(Some(location)) => location,
+            Ok(None) => return Ok(error_text("give a location or coords")),
+            Err(result) => return Ok(result),
+        };
+        self.session
+            .update_context(|saved| saved.location = Some(location.clone()));
         Ok(success_text(format!(
             "Current location set to: {}",
             location
@@ -331,13 +491,39 @@ impl DVBServer {
         &self,
         Parameters(args::DestinationInfo { destination }): Parameters<args::DestinationInfo>,
     ) -> Result<CallToolResult, McpError> {
-        *self.user_destination.lock().await = Some(destination.clone());
+        self.session
+            .update_context(|saved| saved.destination = Some(destination.clone()));
         Ok(success_text(format!("Destination set to: {}", destination)))
     }
 
+    #[tool(
+        description = "Save the user's accessibility needs (`mobility`) and route preferences (`preferences`), e.g. when they mention a wheelchair or want to avoid changes. get_route_details and plan_departure use them whenever a request leaves them out. Only what is given is replaced; reset_context clears them."
+    )]
+    async fn set_preferences(
+        &self,
+        Parameters(args::SetPreferencesRequest {
+            mobility,
+            preferences,
+        }): Parameters<args::SetPreferencesRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        if mobility.is_none() && preferences.is_none() {
+            return Ok(error_text("give mobility or preferences"));
+        }
+        let saved = self.session.update_context(|saved| {
+            if mobility.is_some() {
+                saved.mobility = mobility;
+            }
+            if preferences.is_some() {
+                saved.preferences = preferences;
+            }
+            saved.clone()
+        });
+        Ok(success_json(&UserContext::from(saved)))
+    }
+
     #[tool(description = "Returns the current local time in ISO8601 (RFC3339) format.")]
     async fn now(&self) -> Result<CallToolResult, McpError> {
-        let now = chrono::Local::now().to_rfc3339();
+        let now = self.clock.now().to_rfc3339();
         Ok(success_text(now))
     }
 
@@ -356,9 +542,11 @@ impl DVBServer {
     #[tool(description = "Search for POIs by name and get OpenStreetMap links.")]
     async fn osm_links_from_query(
         &self,
+        context: RequestContext<RoleServer>,
         Parameters(args::FindPoiRequest { rough_poi_name }): Parameters<args::FindPoiRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let points = match dvb::find_pois(&rough_poi_name).await {
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        let points = match backend.find_pois(&rough_poi_name).await {
             Ok(response) => response.into_inner().points,
             Err(error) => {
                 return Ok(error_text(format!(
@@ -386,14 +574,13 @@ impl DVBServer {
     }
 
     #[tool(
-        description = "Clear the stored origin, location, and destination for journey planning; will be requested again on next search."
+        description = "Clear the stored origin, location, destination and route preferences for journey planning; will be requested again on next search."
     )]
     async fn reset_context(&self) -> Result<CallToolResult, McpError> {
-        *self.user_origin.lock().await = None;
-        *self.user_location.lock().await = None;
-        *self.user_destination.lock().await = None;
+        self.session
+            .update_context(|saved| *saved = Default::default());
         Ok(success_text(
-            "User origin, location, and destination reset. They will be requested again when needed.",
+            "User origin, location, destination and route preferences reset. They will be requested again when needed.",
         ))
     }
 
@@ -402,11 +589,13 @@ impl DVBServer {
     )]
     async fn find_stations(
         &self,
+        context: RequestContext<RoleServer>,
         Parameters(args::FindStationRequest { rough_stop_name }): Parameters<
             args::FindStationRequest,
         >,
     ) -> Result<CallToolResult, McpError> {
-        let found = match dvb::find_stops(&rough_stop_name).await {
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        let found = match backend.find_stops(&rough_stop_name).await {
             Ok(found) => found,
             Err(error) => {
                 return Ok(error_text(format!(
@@ -419,15 +608,24 @@ impl DVBServer {
     }
 
     #[tool(
-        description = "Find tram stops, bus stops, or train stations near a specified location or landmark in Dresden."
+        description = "Find tram stops, bus stops, or train stations near a specified location or landmark in Dresden, or near a GPS position given as `coords`."
     )]
     async fn find_nearby_stations(
         &self,
-        Parameters(args::FindNearbyStationRequest { rough_stop_name }): Parameters<
-            args::FindNearbyStationRequest,
-        >,
+        context: RequestContext<RoleServer>,
+        Parameters(args::FindNearbyStationRequest {
+            rough_stop_name,
+            coords,
+        }): Parameters<args::FindNearbyStationRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let found = match dvb::find_nearby_stops(&rough_stop_name).await {
+        let rough_stop_name =
+            match place_argument("rough_stop_name", rough_stop_name, "coords", coords) {
+                Ok(Some(query)) => query,
+                Ok(None) => return Ok(error_text("give a rough_stop_name or coords")),
+                Err(result) => return Ok(result),
+            };
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        let found = match backend.find_nearby_stops(&rough_stop_name).await {
             Ok(found) => found,
             Err(error) => {
                 return Ok(error_text(format!(
@@ -445,9 +643,11 @@ impl DVBServer {
     )]
     async fn find_pois(
         &self,
+        context: RequestContext<RoleServer>,
         Parameters(args::FindPoiRequest { rough_poi_name }): Parameters<args::FindPoiRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let found = match dvb::find_pois(&rough_poi_name).await {
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        let found = match backend.find_pois(&rough_poi_name).await {
             Ok(found) => found,
             Err(error) => {
                 return Ok(error_text(format!(
@@ -459,39 +659,46 @@ impl DVBServer {
         Ok(success_json(&*found))
     }
     #[tool(
-        description = "Get upcoming departures from a specified stop or station in Dresden. Optionally filter by mode of transport and limit the number of results."
+        description = "Get upcoming departures from a specified stop or station in Dresden, given by `stop_id` or by `stop_name`. Optionally filter by mode of transport and limit the number of results. `format: \"markdown\"` returns a ready-made departure table. Set `time` to see the board at a later (or earlier) time and `is_arrival` to list arrivals instead of departures. `lines`, `direction` and `platform` narrow the board down, e.g. to line 11 towards Bühlau; `limit` then counts matching departures only."
     )]
     async fn monitor_departures(
         &self,
+        context: RequestContext<RoleServer>,
         Parameters(args::MonitorDeparturesRequest {
             stop_name,
             stop_id,
             mot,
             limit,
+            board,
+            filter,
+            format,
         }): Parameters<args::MonitorDeparturesRequest>,
     ) -> Result<CallToolResult, McpError> {
-        // Parse Mot if provided
-        let mot_filter = mot.as_ref().map(|mot_list| {
-            mot_list
-                .iter()
-                .filter_map(|m| match m.as_str() {
-                    "Tram" => Some(dvb::Mot::Tram),
-                    "Bus" => Some(dvb::Mot::Bus),
-                    "Ferry" => Some(dvb::Mot::Ferry),
-                    "Train" => Some(dvb::Mot::Train),
-                    _ => None,
-                })
-                .collect::<Vec<_>>()
-        });
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        let stop_id = match (stop_id, &stop_name) {
+            (Some(stop_id), _) => stop_id,
+            (None, Some(stop_name)) => {
+                match self.waypoint(&backend, "stop_name", stop_name, 0).await {
+                    Ok(stop) => stop.stop_id,
+                    Err(result) => return Ok(result),
+                }
+            }
+            (None, None) => return Ok(error_text("either stop_id or stop_name is required")),
+        };
+
+        let mot_filter = mot.as_deref().map(args::Mot::expand);
 
+        let time = board.vvo_time();
         let monitor_params = dvb::monitor::Params {
             stopid: &stop_id,
             mot: mot_filter.as_deref(),
             limit,
-            ..Default::default()
+            time: time.as_deref(),
+            isarrival: board.is_arrival,
+            shorttermchanges: board.shorttermchanges,
         };
 
-        let departures = match dvb::monitor::departure_monitor(monitor_params).await {
+        let departures = match board::departures(&backend, monitor_params, &filter).await {
             Ok(deps) => deps,
             Err(error) => {
                 return Ok(error_text(format!(
@@ -500,40 +707,58 @@ impl DVBServer {
             }
         };
 
-        Ok(success_json(&departures))
+        match format.unwrap_or_default() {
+            args::OutputFormat::Json => Ok(success_json(&departures)),
+            args::OutputFormat::Markdown => Ok(success_markdown(markdown::departures(
+                &departures,
+                self.clock.now(),
+            ))),
+        }
     }
     #[tool(
         description = "List all tram, bus, or train lines departing from a specified stop or station in Dresden."
     )]
     async fn list_lines(
         &self,
-        Parameters(args::LinesRequest { point_id }): Parameters<args::LinesRequest>,
+        context: RequestContext<RoleServer>,
+        Parameters(args::LinesRequest { point_id, mot }): Parameters<args::LinesRequest>,
     ) -> Result<CallToolResult, McpError> {
+        let backend = TrackedBackend::new(&*self.backend, &context);
         let start_point_id = if let Some(point_id) = point_id {
             point_id
         } else {
             return Ok(error_text("missing start point"));
         };
-        let lines = match dvb::lines::lines(&start_point_id, None).await {
+        let mut lines = match backend.lines(&start_point_id).await {
             Ok(resp) => resp.into_inner(),
             Err(error) => {
                 return Ok(error_text(format!("failed to resolve lines {error}")));
             }
         };
+        if let Some(mot) = mot {
+            lines
+                .lines
+                .retain(|line| args::Mot::any_includes(&mot, &line.mot));
+        }
 
         Ok(success_json(&lines))
     }
 
     #[tool(
-        description = "Get detailed information for a specific trip, including all stops and times. Time must be an ISO8601 string."
+        description = "Get the progress of a specific trip: every stop with its planned and expected time and whether the vehicle has passed it, is there now or has yet to get there, plus the next stop and the current delay. Give `target_stop` (id or name) to get the expected arrival there. Time must be an ISO8601 string. `format: \"markdown\"` returns the trip progress as a ready-made list.",
+        output_schema = rmcp::handler::server::tool::schema_for_output::<TripProgress>()
+            .expect("trip progress is a JSON object")
     )]
     async fn get_trip_details(
         &self,
+        context: RequestContext<RoleServer>,
         Parameters(args::TripDetailsRequest {
             trip_id,
             time,
             stop_id,
             mapdata,
+            target_stop,
+            format,
         }): Parameters<args::TripDetailsRequest>,
     ) -> Result<CallToolResult, McpError> {
         let dvb_time = dvb::DvbTime::from(time);
@@ -545,102 +770,324 @@ impl DVBServer {
             mapdata,
         };
 
-        let trip = match dvb::trip::trip_details(&params).await {
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        let trip = match backend.trip_details(&params).await {
             Ok(resp) => resp,
             Err(e) => return Ok(error_text(format!("Failed to fetch trip details: {e}"))),
         };
 
-        Ok(success_json(&*trip))
+        let progress = TripProgress::new(&trip, self.clock.now(), target_stop.as_deref());
+        if let Some(target_stop) = target_stop
+            && progress.target.is_none()
+        {
+            return Ok(error_text(format!(
+                "{target_stop:?} is not a stop of trip {trip_id}"
+            )));
+        }
+
+        let mut result = CallToolResult::structured(serde_json::to_value(&progress).unwrap());
+        if format == Some(args::OutputFormat::Markdown) {
+            result.content = vec![Content::text(markdown::trip(&progress))];
+        }
+        Ok(result)
     }
 
     #[tool(
-        description = "Query possible routes between two stops in Dresden. Returns possible trips, departure and arrival info, etc."
+        description = "Query possible routes between two stops in Dresden. Returns possible trips, departure and arrival info, etc. Origin and destination may be omitted if the user's location/origin or destination are saved; `from_context` in the result lists the values taken from there. `origin_coords` and `destination_coords` take GPS positions instead, e.g. where the user's phone is. With several via stops or dwell times the result is split into `sections`, one per part of the journey. Each connection is summarized by default; `detail` \"legs\" adds its rides and walks (line, direction, stops, delays) with a `trip_id` for get_trip_details, \"full\" also notices about each ride. `format: \"markdown\"` returns the connections as ready-made Markdown. `mobility` and `preferences` default to the ones saved with set_preferences.",
+        output_schema = rmcp::handler::server::tool::schema_for_output::<RouteDetails>()
+            .expect("route details are a JSON object")
     )]
     async fn get_route_details(
         &self,
+        context: RequestContext<RoleServer>,
         Parameters(args::RouteRequest {
             origin,
+            origin_coords,
             destination,
+            destination_coords,
             time,
             isarrivaltime,
             shorttermchanges,
             format,
+            detail,
             via,
+            mobility,
+            preferences,
         }): Parameters<args::RouteRequest>,
     ) -> Result<CallToolResult, McpError> {
-        let dvb_time = dvb::DvbTime::from(time);
-
-        let origin_id = match lookup_stop_id(&origin).await {
-            Ok(resp) => resp,
-            Err(e) => {
-                return Ok(error_text(format!("Failed to fetch origin id : {e}")));
-            }
+        let origin = match place_argument("origin", origin, "origin_coords", origin_coords) {
+            Ok(origin) => origin,
+            Err(result) => return Ok(result),
+        };
+        let destination = match place_argument(
+            "destination",
+            destination,
+            "destination_coords",
+            destination_coords,
+        ) {
+            Ok(destination) => destination,
+            Err(result) => return Ok(result),
+        };
+        let saved = self.session.context();
+        let mut from_context = Vec::new();
+        let Some(origin) = or_from_context(origin, saved.route_origin(), &mut from_context) else {
+            return Ok(error_text(
+                "no origin given and neither location nor origin saved in the user context",
+            ));
+        };
+        let Some(destination) =
+            or_from_context(destination, saved.route_destination(), &mut from_context)
+        else {
+            return Ok(error_text(
+                "no destination given and none saved in the user context",
+            ));
         };
 
-        let destination_id = match lookup_stop_id(&destination).await {
-            Ok(resp) => resp,
-            Err(e) => {
-                return Ok(error_text(format!("Failed to fetch destination id : {e}")));
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        let mut stops = Vec::new();
+        match self.waypoint(&backend, "origin", &origin, 0).await {
+            Ok(stop) => stops.push(stop),
+            Err(result) => return Ok(result),
+        }
+        for (
+            index,
+            args::ViaStop {
+                stop,
+                dwell_minutes,
+            },
+        ) in via
+            .map(args::Via::into_stops)
+            .into_iter()
+            .flatten()
+            .enumerate()
+        {
+            let field = format!("via[{index}]");
+            match self
+                .waypoint(&backend, &field, &stop, dwell_minutes.unwrap_or(0))
+                .await
+            {
+                Ok(stop) => stops.push(stop),
+                Err(result) => return Ok(result),
             }
-        };
+        }
+        match self
+            .waypoint(&backend, "destination", &destination, 0)
+            .await
+        {
+            Ok(stop) => stops.push(stop),
+            Err(result) => return Ok(result),
+        }
 
-        let params = dvb::route::Params {
-            origin: &origin_id,
-            destination: &destination_id,
-            time: dvb_time,
+        let options = RouteOptions {
             isarrivaltime: isarrivaltime.unwrap_or(false),
             shorttermchanges: shorttermchanges.unwrap_or(true),
-            format: format.as_deref().unwrap_or("json"),
-            via: via.as_deref(),
-            mobility_settings: None,
-            standard_settings: None,
+            mobility: mobility.or(saved.mobility).map(Into::into),
+            standard: preferences.or(saved.preferences).map(Into::into),
         };
-
-        let route = match dvb::route::route_details(&params).await {
-            Ok(resp) => resp,
+        let legs = match itinerary::plan(&backend, &stops, time.fixed_offset(), &options).await {
+            Ok(legs) => legs,
             Err(e) => return Ok(error_text(format!("Failed to fetch route details: {e}"))),
         };
 
-        // Strip out partial_routes from each Route before returning
-        let mut routes = route.into_inner();
-        for r in &mut routes.routes {
-            r.partial_routes = None;
+        let details = RouteDetails::new(&legs, detail.unwrap_or_default(), from_context);
+        let mut result = CallToolResult::structured(serde_json::to_value(&details).unwrap());
+        if format == Some(args::OutputFormat::Markdown) {
+            let mut text = markdown::routes(&legs);
+            for value in &details.from_context {
+                text.push_str(&format!(
+                    "\n_{} taken from the saved {}: {}_\n",
+                    value.field, value.source, value.value
+                ));
+            }
+            result.content = vec![Content::text(text)];
+        }
+        Ok(result)
+    }
+
+    #[tool(
+        description = "Work out when to leave to be at a destination by `arrive_by`. Connections are planned backwards to arrive `buffer_minutes` (default 5) early; the latest one that can still be caught is returned with `leave_at` (walks to the first stop included) and `leave_in_minutes`, plus the one before it as `fallback`. Origin and destination may be omitted if the user's location/origin or destination are saved; `from_context` in the result lists the values taken from there. Routes are planned with the preferences saved with set_preferences. Time must be an ISO8601 string.",
+        output_schema = rmcp::handler::server::tool::schema_for_output::<DeparturePlan>()
+            .expect("departure plan is a JSON object")
+    )]
+    async fn plan_departure(
+        &self,
+        context: RequestContext<RoleServer>,
+        Parameters(args::DeparturePlanRequest {
+            destination,
+            arrive_by,
+            origin,
+            buffer_minutes,
+        }): Parameters<args::DeparturePlanRequest>,
+    ) -> Result<CallToolResult, McpError> {
+        let saved = self.session.context();
+        let mut from_context = Vec::new();
+        let Some(origin) = or_from_context(origin, saved.route_origin(), &mut from_context) else {
+            return Ok(error_text(
+                "no origin given and neither location nor origin saved in the user context",
+            ));
+        };
+        let Some(destination) =
+            or_from_context(destination, saved.route_destination(), &mut from_context)
+        else {
+            return Ok(error_text(
+                "no destination given and none saved in the user context",
+            ));
+        };
+
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        let mut stops = Vec::new();
+        for (field, query) in [("origin", &origin), ("destination", &destination)] {
+            match self.waypoint(&backend, field, query, 0).await {
+                Ok(stop) => stops.push(stop),
+                Err(result) => return Ok(result),
+            }
         }
 
-        Ok(success_json(&routes))
+        let buffer_minutes = buffer_minutes.unwrap_or(DEFAULT_BUFFER_MINUTES);
+        let latest_arrival = arrive_by - chrono::Duration::minutes(buffer_minutes.into());
+        let options = RouteOptions {
+            isarrivaltime: true,
+            shorttermchanges: true,
+            mobility: saved.mobility.map(Into::into),
+            standard: saved.preferences.map(Into::into),
+        };
+        let legs = match itinerary::plan(&backend, &stops, latest_arrival, &options).await {
+            Ok(legs) => legs,
+            Err(e) => return Ok(error_text(format!("Failed to fetch route details: {e}"))),
+        };
+
+        let now = self.clock.now();
+        let Some(plan) = legs
+            .first()
+            .and_then(|leg| DeparturePlan::new(leg, arrive_by, buffer_minutes, now, from_context))
+        else {
+            return Ok(error_text(format!(
+                "no connection from {origin} to {destination} arriving by {} can still be caught",
+                latest_arrival.format("%H:%M")
+            )));
+        };
+        Ok(CallToolResult::structured(
+            serde_json::to_value(&plan).unwrap(),
+        ))
     }
 
     #[tool(
-        description = "Look up the stop ID for a given stop name or query string in Dresden. Returns the stop ID if found."
+        description = "Look up the stop ID for a given stop name or query string in Dresden. Returns the stop ID if found. If several stops match, the user is asked to pick one; clients without elicitation get status \"ambiguous\" and a list of candidates instead."
     )]
     async fn lookup_stop_id(
         &self,
+        context: RequestContext<RoleServer>,
         Parameters(args::FindStationRequest { rough_stop_name }): Parameters<
             args::FindStationRequest,
         >,
     ) -> Result<CallToolResult, McpError> {
-        let found = match dvb::find_stops(&rough_stop_name).await {
-            Ok(found) => found,
-            Err(error) => {
-                return Ok(error_text(format!(
-                    "failed to find stop {rough_stop_name:?} {error}"
-                )));
-            }
-        };
-        let stop = match found.points.first() {
-            Some(stop) => stop,
-            None => {
-                return Ok(error_text(format!(
-                    "no search results for {rough_stop_name:?}"
-                )));
-            }
-        };
-
-        Ok(success_json(&serde_json::json!({ "stop_id": stop.id })))
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        match resolve_stop(
+            &backend,
+            backend.peer(),
+            "rough_stop_name",
+            &rough_stop_name,
+        )
+        .await
+        {
+            Ok(StopResolution::Resolved(stop)) => Ok(success_json(
+                &serde_json::json!({ "stop_id": stop.stop_id }),
+            )),
+            Ok(StopResolution::Ambiguous(ambiguous)) => Ok(success_json(&ambiguous)),
+            Err(error) => Ok(error_text(format!(
+                "failed to find stop {rough_stop_name:?} {error}"
+            ))),
+        }
     }
 }
 
 impl DVBServer {
+    /// Create a server whose first session keeps its user context in `contexts`
+    /// and fetches transit data from `backend`
+    pub fn new(contexts: UserContextStore, backend: Arc<dyn TransitBackend>) -> Self {
+        Self {
+            tool_router: Self::tool_router(),
+            prompt_router: Self::prompt_router(),
+            session: Arc::new(contexts.open_session()),
+            subscriptions: Arc::new(Subscriptions::new(backend.clone()).open_session()),
+            completions: Default::default(),
+            backend,
+            clock: Clock::system(),
+        }
+    }
+
+    /// Use `clock` instead of the system time
+    pub fn with_clock(mut self, clock: Clock) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Poll subscribed resources every `interval`
+    #[cfg(test)]
+    pub fn with_poll_interval(mut self, interval: std::time::Duration) -> Self {
+        let subscriptions = Subscriptions::new(self.backend.clone()).with_poll_interval(interval);
+        self.subscriptions = Arc::new(subscriptions.open_session());
+        self
+    }
+
+    /// Resolve the stop given for the prompt argument `field`
+    async fn prompt_stop(
+        &self,
+        backend: &TrackedBackend<'_>,
+        field: &str,
+        query: &str,
+    ) -> Result<StopCandidate, McpError> {
+        match resolve_stop(backend, backend.peer(), field, query).await {
+            Ok(StopResolution::Resolved(stop)) => Ok(stop),
+            Ok(StopResolution::Ambiguous(ambiguous)) => Err(McpError::invalid_params(
+                ambiguous.message.clone(),
+                serde_json::to_value(&ambiguous).ok(),
+            )),
+            Err(e) => Err(McpError::invalid_params(
+                format!("Failed to resolve {field} {query:?}: {e}"),
+                None,
+            )),
+        }
+    }
+
+    /// Resolve the stop given for the tool argument `field`
+    ///
+    /// Fails with the tool result to return instead: an error, or the candidates of an ambiguous name.
+    async fn waypoint(
+        &self,
+        backend: &TrackedBackend<'_>,
+        field: &str,
+        query: &str,
+        dwell_minutes: u32,
+    ) -> Result<Waypoint, CallToolResult> {
+        match resolve_stop(backend, backend.peer(), field, query).await {
+            Ok(StopResolution::Resolved(stop)) => Ok(Waypoint {
+                name: stop.name,
+                stop_id: stop.stop_id,
+                dwell_minutes,
+            }),
+            Ok(StopResolution::Ambiguous(ambiguous)) => Err(success_json(&ambiguous)),
+            Err(e) => Err(error_text(format!(
+                "Failed to resolve {field} {query:?}: {e}"
+            ))),
+        }
+    }
+
+    /// Create a server for another client session
+    ///
+    /// Shares the user context store with `self`, but saved context is kept separate per session.
+    pub fn new_session(&self) -> Self {
+        Self {
+            tool_router: self.tool_router.clone(),
+            prompt_router: self.prompt_router.clone(),
+            session: Arc::new(self.session.store().open_session()),
+            subscriptions: Arc::new(self.subscriptions.subscriptions().open_session()),
+            completions: Default::default(),
+            backend: self.backend.clone(),
+            clock: self.clock.clone(),
+        }
+    }
+
     /// List all available tools
     pub fn list_tools(&self) {
         println!("Available Tools:");
@@ -1,8 +1,12 @@
 //! Argument types for MCP tools
 
 use chrono::{DateTime, FixedOffset};
-use dvb::point::Point;
-use rmcp::{elicit_safe, schemars::JsonSchema};
+use dvb::{
+    monitor::Departure,
+    point::Point,
+    route::{MobilitySettings, StandardSettings},
+};
+use rmcp::{elicit_safe, schemars::JsonSchema, serde_json};
 use serde::{Deserialize, Serialize};
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
@@ -37,6 +41,8 @@ pub struct LinesRequest {
     // pub start_query: Option<String>,
     /// The ID of a point. Can be found via `lookup_point` function.
     pub point_id: Option<String>,
+    /// Only list lines of these modes of transport (e.g., ["Tram"])
+    pub mot: Option<Vec<Mot>>,
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
@@ -46,7 +52,26 @@ pub struct FindStationRequest {
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct FindNearbyStationRequest {
-    pub rough_stop_name: String,
+    /// Place or landmark to search near
+    pub rough_stop_name: Option<String>,
+    /// GPS position to search near, instead of `rough_stop_name`
+    pub coords: Option<Wgs84Coords>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SetLocationRequest {
+    /// User's current location
+    pub location: Option<String>,
+    /// User's current GPS position, instead of `location`
+    pub coords: Option<Wgs84Coords>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct SetPreferencesRequest {
+    /// Accessibility needs to plan every route with
+    pub mobility: Option<MobilityOptions>,
+    /// Changes, walking and modes of transport to plan every route with
+    pub preferences: Option<RoutePreferences>,
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
@@ -57,14 +82,163 @@ pub struct FindPoiRequest {
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct MonitorDeparturesRequest {
-    /// Partial or full stop name to search for
+    /// Partial or full stop name to search for, used when `stop_id` is missing
     pub stop_name: Option<String>,
     /// The ID of a point. Can be found via `lookup_point` function.
-    pub stop_id: String,
+    pub stop_id: Option<String>,
     /// Optional list of modes of transport (e.g., ["Tram", "Bus"])
-    pub mot: Option<Vec<String>>,
+    pub mot: Option<Vec<Mot>>,
     /// Optional limit for number of departures
     pub limit: Option<u32>,
+    #[serde(flatten)]
+    pub board: BoardOptions,
+    #[serde(flatten)]
+    pub filter: DepartureFilter,
+    /// "markdown" for a ready-made departure table instead of JSON
+    pub format: Option<OutputFormat>,
+}
+
+/// How much of a connection `get_route_details` returns
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum DetailLevel {
+    /// Duration, changes, walking time, departure and arrival
+    #[default]
+    Summary,
+    /// Also every ride and walk with line, platforms, stops passed and trip id
+    Legs,
+    /// Also notices about every ride
+    Full,
+}
+
+/// How a tool presents its result
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    /// The VVO response as JSON
+    #[default]
+    Json,
+    /// A Markdown view to show to the user as it is
+    Markdown,
+}
+
+/// Departures to keep on a board, applied after fetching it
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct DepartureFilter {
+    /// Only these lines (e.g., ["11", "62"])
+    pub lines: Option<Vec<String>>,
+    /// Only departures whose direction contains this text (e.g., "Bühlau")
+    pub direction: Option<String>,
+    /// Only departures from this platform or track (e.g., "3")
+    pub platform: Option<String>,
+}
+
+impl DepartureFilter {
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_none() && self.direction.is_none() && self.platform.is_none()
+    }
+
+    pub fn matches(&self, departure: &Departure) -> bool {
+        let line_matches = self.lines.as_ref().is_none_or(|lines| {
+            lines
+                .iter()
+                .any(|line| line.trim().eq_ignore_ascii_case(&departure.line_name))
+        });
+        let direction_matches = self.direction.as_ref().is_none_or(|direction| {
+            departure
+                .direction
+                .to_lowercase()
+                .contains(&direction.trim().to_lowercase())
+        });
+        let platform_matches = self.platform.as_ref().is_none_or(|platform| {
+            departure
+                .platform
+                .as_ref()
+                .is_some_and(|actual| actual.name.eq_ignore_ascii_case(platform.trim()))
+        });
+        line_matches && direction_matches && platform_matches
+    }
+}
+
+/// Which moment a departure board shows, shared by `monitor_departures` and `dvb://departures/{stop_id}`
+#[derive(Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct BoardOptions {
+    /// Show the board at this time instead of now (ISO8601, e.g. "2026-10-16T23:40:00+02:00")
+    pub time: Option<DateTime<FixedOffset>>,
+    /// List vehicles arriving around `time` instead of departing
+    pub is_arrival: Option<bool>,
+    /// Include short-term changes such as diversions and cancellations
+    pub shorttermchanges: Option<bool>,
+}
+
+impl BoardOptions {
+    /// Parse the query part of a departures resource URI, e.g. `time=...&is_arrival=true`
+    pub fn from_query(query: &str) -> Result<Self, String> {
+        let mut options = Self::default();
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, "true"));
+            let value = percent_decode(value);
+            match key {
+                "time" => {
+                    let time = DateTime::parse_from_rfc3339(&value)
+                        .map_err(|error| format!("invalid time {value:?}: {error}"))?;
+                    options.time = Some(time);
+                }
+                "is_arrival" => options.is_arrival = Some(parse_bool(key, &value)?),
+                "shorttermchanges" => options.shorttermchanges = Some(parse_bool(key, &value)?),
+                _ => return Err(format!("unknown parameter {key:?}")),
+            }
+        }
+        Ok(options)
+    }
+
+    /// `time` the way the VVO departure monitor expects it
+    pub fn vvo_time(&self) -> Option<String> {
+        self.time.map(|time| time.to_rfc3339())
+    }
+}
+
+fn parse_bool(key: &str, value: &str) -> Result<bool, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{key} must be true or false, not {value:?}"))
+}
+
+/// Decode `%XX` escapes, leaving everything else (including `+`) as it is
+pub fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut index = 0;
+    while index < bytes.len() {
+        let escaped = bytes
+            .get(index + 1..index + 3)
+            .filter(|_| bytes[index] == b'%')
+            .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok());
+        match escaped {
+            Some(byte) => {
+                decoded.push(byte);
+                index += 3;
+            }
+            None => {
+                decoded.push(bytes[index]);
+                index += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+/// Escape everything but unreserved characters and `:` for use in a resource uri
+pub fn percent_encode(value: &str) -> String {
+    let mut encoded = String::with_capacity(value.len());
+    for byte in value.bytes() {
+        if byte.is_ascii_alphanumeric() || b"-._~:".contains(&byte) {
+            encoded.push(byte.into());
+        } else {
+            encoded.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    encoded
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
@@ -74,17 +248,361 @@ pub struct TripDetailsRequest {
     /// The ID of a point. Can be found via `lookup_point` function.
     pub stop_id: String,
     pub mapdata: Option<bool>,
+    /// Stop id or name to get the expected arrival time for, e.g. where the user gets off
+    pub target_stop: Option<String>,
+    /// "markdown" for a ready-made progress list instead of JSON
+    pub format: Option<OutputFormat>,
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct RouteRequest {
-    pub origin: String,
-    pub destination: String,
+    /// Start stop, defaults to the user's saved location, then their saved origin
+    pub origin: Option<String>,
+    /// GPS position to start from, instead of `origin`
+    pub origin_coords: Option<Wgs84Coords>,
+    /// End stop, defaults to the user's saved destination
+    pub destination: Option<String>,
+    /// GPS position to go to, instead of `destination`
+    pub destination_coords: Option<Wgs84Coords>,
     pub time: DateTime<chrono::Local>,
     pub isarrivaltime: Option<bool>,
     pub shorttermchanges: Option<bool>,
-    pub format: Option<String>,
-    pub via: Option<String>,
+    /// "markdown" for a ready-made list of connections instead of JSON
+    pub format: Option<OutputFormat>,
+    /// How much of each connection to return, defaults to "summary"
+    pub detail: Option<DetailLevel>,
+    /// Stops the route has to pass, in order: one stop name or id, or a list of them
+    pub via: Option<Via>,
+    /// Accessibility needs, e.g. for wheelchairs or prams, defaults to the saved ones
+    pub mobility: Option<MobilityOptions>,
+    /// Changes, walking and modes of transport, defaults to the saved ones
+    pub preferences: Option<RoutePreferences>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct DeparturePlanRequest {
+    /// End stop, defaults to the user's saved destination
+    pub destination: Option<String>,
+    /// When the user wants to be at the destination
+    pub arrive_by: DateTime<FixedOffset>,
+    /// Start stop, defaults to the user's saved location, then their saved origin
+    pub origin: Option<String>,
+    /// Minutes to arrive early, defaults to 5
+    pub buffer_minutes: Option<u32>,
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ViaStop {
+    /// Stop name or stop id
+    pub stop: String,
+    /// Minutes to stay at the stop before continuing
+    pub dwell_minutes: Option<u32>,
+}
+
+/// Via stops as given in a request, a single name is what earlier versions took
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum Via {
+    /// Stop name or stop id
+    Stop(String),
+    /// Stops in order, by name or id or with a dwell time
+    Stops(Vec<ViaEntry>),
+}
+
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ViaEntry {
+    /// Stop name or stop id
+    Stop(String),
+    WithDwell(ViaStop),
+}
+
+impl Via {
+    pub fn into_stops(self) -> Vec<ViaStop> {
+        let entries = match self {
+            Via::Stop(stop) => vec![ViaEntry::Stop(stop)],
+            Via::Stops(entries) => entries,
+        };
+        entries
+            .into_iter()
+            .map(|entry| match entry {
+                ViaEntry::Stop(stop) => ViaStop {
+                    stop,
+                    dwell_minutes: None,
+                },
+                ViaEntry::WithDwell(stop) => stop,
+            })
+            .collect()
+    }
+}
+
+/// How well the route has to suit limited mobility
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum MobilityRestriction {
+    /// No restrictions
+    None,
+    /// Suitable for rollators and prams
+    Medium,
+    /// Suitable for wheelchairs
+    High,
+    /// Use `solid_stairs`, `escalators` and `least_change`
+    Individual,
+}
+
+/// Step height when boarding vehicles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Entrance {
+    Any,
+    SmallStep,
+    NoStep,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct MobilityOptions {
+    pub restriction: MobilityRestriction,
+    /// Whether stairs are acceptable, only with `Individual` restriction
+    pub solid_stairs: Option<bool>,
+    /// Whether escalators are acceptable, only with `Individual` restriction
+    pub escalators: Option<bool>,
+    /// Prefer routes with fewer changes, only with `Individual` restriction
+    pub least_change: Option<bool>,
+    pub entrance: Option<Entrance>,
+}
+
+impl From<MobilityOptions> for MobilitySettings {
+    fn from(options: MobilityOptions) -> Self {
+        Self {
+            mobility_restriction: wire_name(options.restriction),
+            solid_stairs: options.solid_stairs,
+            escalators: options.escalators,
+            least_change: options.least_change,
+            entrance: options.entrance.and_then(wire_name),
+        }
+    }
+}
+
+/// Maximum number of changes between vehicles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum MaxChanges {
+    Unlimited,
+    Two,
+    One,
+    None,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum WalkingSpeed {
+    VerySlow,
+    Slow,
+    Normal,
+    Fast,
+    VeryFast,
+}
+
+/// Mode of transport, as used by the VVO API
+///
+/// Unknown names are rejected instead of being ignored.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Mot {
+    Tram,
+    /// Any bus; departure boards and lines usually say `CityBus` or `IntercityBus`
+    Bus,
+    CityBus,
+    IntercityBus,
+    /// S-Bahn
+    SuburbanRailway,
+    Train,
+    /// Funiculars and the suspension railway
+    Cableway,
+    Ferry,
+    /// Shared taxi on demand (Anrufsammeltaxi)
+    HailedSharedTaxi,
+    PlusBus,
+    Footpath,
+    RapidTransit,
+    BusOnRequest,
+    RegioBus,
+    CitizenBus,
+    DemandBus,
+    SchoolBus,
+    ClockBus,
+    OverheadRailway,
+    Taxi,
+    StayForConnection,
+    StayInVehicle,
+    MobilityStairsUp,
+    MobilityStairsDown,
+    MobilityElevatorUp,
+    MobilityElevatorDown,
+    MobilityEscalatorUp,
+    MobilityEscalatorDown,
+    MobilityRampUp,
+    MobilityRampDown,
+}
+
+impl Mot {
+    /// Modes the route planner can be restricted to
+    pub const ROUTE_PLANNER: [Mot; 8] = [
+        Mot::Tram,
+        Mot::CityBus,
+        Mot::IntercityBus,
+        Mot::SuburbanRailway,
+        Mot::Train,
+        Mot::Cableway,
+        Mot::Ferry,
+        Mot::HailedSharedTaxi,
+    ];
+
+    /// Every mode, in declaration order
+    pub const ALL: [Mot; 30] = [
+        Mot::Tram,
+        Mot::Bus,
+        Mot::CityBus,
+        Mot::IntercityBus,
+        Mot::SuburbanRailway,
+        Mot::Train,
+        Mot::Cableway,
+        Mot::Ferry,
+        Mot::HailedSharedTaxi,
+        Mot::PlusBus,
+        Mot::Footpath,
+        Mot::RapidTransit,
+        Mot::BusOnRequest,
+        Mot::RegioBus,
+        Mot::CitizenBus,
+        Mot::DemandBus,
+        Mot::SchoolBus,
+        Mot::ClockBus,
+        Mot::OverheadRailway,
+        Mot::Taxi,
+        Mot::StayForConnection,
+        Mot::StayInVehicle,
+        Mot::MobilityStairsUp,
+        Mot::MobilityStairsDown,
+        Mot::MobilityElevatorUp,
+        Mot::MobilityElevatorDown,
+        Mot::MobilityEscalatorUp,
+        Mot::MobilityEscalatorDown,
+        Mot::MobilityRampUp,
+        Mot::MobilityRampDown,
+    ];
+
+    /// Whether filtering for `self` should keep `other`, `Bus` stands for all kinds of buses
+    pub fn includes(self, other: Mot) -> bool {
+        self == other
+            || self == Mot::Bus
+                && matches!(
+                    other,
+                    Mot::CityBus
+                        | Mot::IntercityBus
+                        | Mot::PlusBus
+                        | Mot::BusOnRequest
+                        | Mot::RegioBus
+                        | Mot::CitizenBus
+                        | Mot::DemandBus
+                        | Mot::SchoolBus
+                        | Mot::ClockBus
+                )
+    }
+
+    /// Whether filtering for any of `filter` should keep `mot`
+    pub fn any_includes(filter: &[Mot], mot: &dvb::Mot) -> bool {
+        let Some(mot) = Mot::from_dvb(mot) else {
+            return false;
+        };
+        filter.iter().any(|wanted| wanted.includes(mot))
+    }
+
+    /// The modes to ask VVO for when filtering for `filter`, with `Bus` spelled out
+    pub fn expand(filter: &[Mot]) -> Vec<dvb::Mot> {
+        Mot::ALL
+            .into_iter()
+            .filter(|&mot| filter.iter().any(|wanted| wanted.includes(mot)))
+            .map(dvb::Mot::from)
+            .collect()
+    }
+
+    /// The same mode as `mot`, `None` for modes added to the dvb crate later
+    pub fn from_dvb(mot: &dvb::Mot) -> Option<Mot> {
+        serde_json::from_value(serde_json::to_value(mot).ok()?).ok()
+    }
+}
+
+impl From<Mot> for dvb::Mot {
+    fn from(mot: Mot) -> Self {
+        match mot {
+            Mot::Tram => dvb::Mot::Tram,
+            Mot::Bus => dvb::Mot::Bus,
+            Mot::CityBus => dvb::Mot::CityBus,
+            Mot::IntercityBus => dvb::Mot::IntercityBus,
+            Mot::SuburbanRailway => dvb::Mot::SuburbanRailway,
+            Mot::Train => dvb::Mot::Train,
+            Mot::Cableway => dvb::Mot::Cableway,
+            Mot::Ferry => dvb::Mot::Ferry,
+            Mot::HailedSharedTaxi => dvb::Mot::HailedSharedTaxi,
+            Mot::PlusBus => dvb::Mot::PlusBus,
+            Mot::Footpath => dvb::Mot::Footpath,
+            Mot::RapidTransit => dvb::Mot::RapidTransit,
+            Mot::BusOnRequest => dvb::Mot::BusOnRequest,
+            Mot::RegioBus => dvb::Mot::RegioBus,
+            Mot::CitizenBus => dvb::Mot::CitizenBus,
+            Mot::DemandBus => dvb::Mot::DemandBus,
+            Mot::SchoolBus => dvb::Mot::SchoolBus,
+            Mot::ClockBus => dvb::Mot::ClockBus,
+            Mot::OverheadRailway => dvb::Mot::OverheadRailway,
+            Mot::Taxi => dvb::Mot::Taxi,
+            Mot::StayForConnection => dvb::Mot::StayForConnection,
+            Mot::StayInVehicle => dvb::Mot::StayInVehicle,
+            Mot::MobilityStairsUp => dvb::Mot::MobilityStairsUp,
+            Mot::MobilityStairsDown => dvb::Mot::MobilityStairsDown,
+            Mot::MobilityElevatorUp => dvb::Mot::MobilityElevatorUp,
+            Mot::MobilityElevatorDown => dvb::Mot::MobilityElevatorDown,
+            Mot::MobilityEscalatorUp => dvb::Mot::MobilityEscalatorUp,
+            Mot::MobilityEscalatorDown => dvb::Mot::MobilityEscalatorDown,
+            Mot::MobilityRampUp => dvb::Mot::MobilityRampUp,
+            Mot::MobilityRampDown => dvb::Mot::MobilityRampDown,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RoutePreferences {
+    pub max_changes: Option<MaxChanges>,
+    pub walking_speed: Option<WalkingSpeed>,
+    /// Longest acceptable walk to or from a stop, in minutes
+    pub footpath_to_stop: Option<u32>,
+    /// Also consider stops close to origin and destination
+    pub include_alternative_stops: Option<bool>,
+    /// Modes of transport the route must not use
+    pub exclude_mot: Option<Vec<Mot>>,
+}
+
+impl From<RoutePreferences> for StandardSettings {
+    fn from(preferences: RoutePreferences) -> Self {
+        let mot = preferences.exclude_mot.map(|excluded| {
+            Mot::ROUTE_PLANNER
+                .into_iter()
+                .filter(|&mot| !excluded.iter().any(|excluded| excluded.includes(mot)))
+                .filter_map(wire_name)
+                .collect()
+        });
+        Self {
+            mot,
+            max_changes: preferences.max_changes.and_then(wire_name),
+            walking_speed: preferences.walking_speed.and_then(wire_name),
+            footpath_to_stop: preferences.footpath_to_stop,
+            include_alternative_stops: preferences.include_alternative_stops,
+            extra_charge: None,
+        }
+    }
+}
+
+/// Name of a unit variant as the VVO API spells it
+fn wire_name<T: Serialize>(value: T) -> Option<String> {
+    serde_json::to_value(value)
+        .ok()?
+        .as_str()
+        .map(str::to_owned)
 }
 
 #[derive(Debug, Serialize, Deserialize, JsonSchema)]
@@ -101,3 +619,124 @@ impl From<Point> for DVBPointCoords {
         }
     }
 }
+
+/// A GPS position
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, JsonSchema)]
+pub struct Wgs84Coords {
+    /// Degrees north, e.g. 51.0405 for Dresden Hauptbahnhof
+    pub latitude: f64,
+    /// Degrees east, e.g. 13.7320 for Dresden Hauptbahnhof
+    pub longitude: f64,
+}
+
+/// Arguments of the `navigation-assistant` prompt
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct NavigationPromptArgs {
+    /// Start stop, defaults to the user's saved location, then their saved origin
+    pub origin: Option<String>,
+    /// End stop, defaults to the user's saved destination
+    pub destination: Option<String>,
+    /// ISO8601 departure time of the attached connections, defaults to now
+    pub time: Option<DateTime<FixedOffset>>,
+}
+
+/// Arguments of the `departure-monitor` prompt
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct DeparturePromptArgs {
+    /// Stop name or id, defaults to the user's saved location
+    pub station: Option<String>,
+}
+
+/// Arguments of the `trip-tracker` prompt
+#[derive(Debug, Default, Deserialize, JsonSchema)]
+pub struct TripPromptArgs {
+    /// Trip id from get_route_details
+    pub trip_id: Option<String>,
+    /// Id of a stop of the trip, required with `trip_id`
+    pub stop_id: Option<String>,
+    /// ISO8601 time the trip passes `stop_id`, defaults to now
+    pub time: Option<DateTime<FixedOffset>>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rmcp::serde_json::json;
+
+    #[test]
+    fn route_options_map_onto_vvo_settings() {
+        let mobility: MobilityOptions = serde_json::from_value(json!({
+            "restriction": "High",
+            "entrance": "NoStep",
+        }))
+        .unwrap();
+        assert_eq!(
+            serde_json::to_value(MobilitySettings::from(mobility)).unwrap(),
+            json!({
+                "mobilityRestriction": "High",
+                "solidStairs": null,
+                "escalators": null,
+                "leastChange": null,
+                "entrance": "NoStep",
+            })
+        );
+
+        let preferences: RoutePreferences = serde_json::from_value(json!({
+            "max_changes": "One",
+            "walking_speed": "VerySlow",
+            "footpath_to_stop": 5,
+            "exclude_mot": ["Bus", "Cableway", "HailedSharedTaxi"],
+        }))
+        .unwrap();
+        let settings = StandardSettings::from(preferences);
+        assert_eq!(settings.max_changes.as_deref(), Some("One"));
+        assert_eq!(settings.walking_speed.as_deref(), Some("VerySlow"));
+        assert_eq!(settings.footpath_to_stop, Some(5));
+        assert_eq!(
+            settings.mot.unwrap(),
+            ["Tram", "SuburbanRailway", "Train", "Ferry"]
+        );
+    }
+
+    #[test]
+    fn board_options_from_resource_query() {
+        let board =
+            BoardOptions::from_query("time=2026-10-16T23:40:00%2B02:00&is_arrival=true").unwrap();
+        assert_eq!(board.vvo_time().unwrap(), "2026-10-16T23:40:00+02:00");
+        assert_eq!(board.is_arrival, Some(true));
+        assert_eq!(board.shorttermchanges, None);
+
+        // `+` is not turned into a space
+        let board = BoardOptions::from_query("time=2026-10-16T23:40:00+02:00").unwrap();
+        assert!(board.time.is_some());
+
+        assert!(BoardOptions::from_query("is_arrival=maybe").is_err());
+        assert!(BoardOptions::from_query("platform=2").is_err());
+        assert!(BoardOptions::from_query("").is_ok());
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        let result =
+            serde_json::from_value::<RoutePreferences>(json!({ "walking_speed": "Sprint" }));
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn via_takes_a_name_or_a_list() {
+        let stops = |via| {
+            serde_json::from_value::<Via>(via)
+                .unwrap()
+                .into_stops()
+                .into_iter()
+                .map(|stop| (stop.stop, stop.dwell_minutes))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(stops(json!("Postplatz")), [("Postplatz".into(), None)]);
+        assert_eq!(
+            stops(json!(["Postplatz", { "stop": "33000013", "dwell_minutes": 10 }])),
+            [("Postplatz".into(), None), ("33000013".into(), Some(10))]
+        );
+        assert!(serde_json::from_value::<Via>(json!(42)).is_err());
+    }
+}
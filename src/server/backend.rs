@@ -0,0 +1,107 @@
+//! Upstream transit data, fetched live from the VVO API or served from recorded responses
+
+use std::{future::Future, pin::Pin};
+
+use dvb::{
+    DvbResponse,
+    lines::Lines,
+    monitor::{self, DepartureMonitor},
+    point::Found,
+    route::{self, Routes},
+    trip::{self, Trip},
+};
+use serde::{Deserialize, Serialize};
+
+pub mod cache;
+pub mod fixture;
+pub mod record;
+pub mod tracked;
+
+pub type BackendFuture<'a, T> = Pin<Box<dyn Future<Output = anyhow::Result<T>> + Send + 'a>>;
+
+/// Upstream endpoints used by the tools
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Endpoint {
+    FindStops,
+    FindNearbyStops,
+    FindPois,
+    Monitor,
+    Trip,
+    Route,
+    Lines,
+}
+
+/// Source of transit data for [`DVBServer`](crate::server::DVBServer)
+pub trait TransitBackend: Send + Sync {
+    fn find_stops<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>>;
+
+    fn find_nearby_stops<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>>;
+
+    fn find_pois<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>>;
+
+    fn departure_monitor<'a>(
+        &'a self,
+        params: monitor::Params<'a>,
+    ) -> BackendFuture<'a, DvbResponse<DepartureMonitor>>;
+
+    fn trip_details<'a>(
+        &'a self,
+        params: &'a trip::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Trip>>;
+
+    fn route_details<'a>(
+        &'a self,
+        params: &'a route::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Routes>>;
+
+    fn lines<'a>(&'a self, stop_id: &'a str) -> BackendFuture<'a, DvbResponse<Lines>>;
+
+    /// Counters of the response cache, `None` if responses are not cached
+    fn cache_stats(&self) -> Option<cache::CacheStats> {
+        None
+    }
+}
+
+/// The live VVO web API
+#[derive(Debug, Default, Clone, Copy)]
+pub struct LiveBackend;
+
+impl TransitBackend for LiveBackend {
+    fn find_stops<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        Box::pin(async move { Ok(dvb::find_stops(query).await?) })
+    }
+
+    fn find_nearby_stops<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        Box::pin(async move { Ok(dvb::find_nearby_stops(query).await?) })
+    }
+
+    fn find_pois<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        Box::pin(async move { Ok(dvb::find_pois(query).await?) })
+    }
+
+    fn departure_monitor<'a>(
+        &'a self,
+        params: monitor::Params<'a>,
+    ) -> BackendFuture<'a, DvbResponse<DepartureMonitor>> {
+        Box::pin(async move { Ok(monitor::departure_monitor(params).await?) })
+    }
+
+    fn trip_details<'a>(
+        &'a self,
+        params: &'a trip::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Trip>> {
+        Box::pin(async move { Ok(trip::trip_details(params).await?) })
+    }
+
+    fn route_details<'a>(
+        &'a self,
+        params: &'a route::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Routes>> {
+        Box::pin(async move { Ok(route::route_details(params).await?) })
+    }
+
+    fn lines<'a>(&'a self, stop_id: &'a str) -> BackendFuture<'a, DvbResponse<Lines>> {
+        Box::pin(async move { Ok(dvb::lines::lines(stop_id, None).await?) })
+    }
+}
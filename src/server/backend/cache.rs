@@ -0,0 +1,320 @@
+//! In-process cache for upstream responses
+//!
+//! Responses are kept in VVO wire format (see [`to_wire`]) and parsed again on every hit,
+//! because the dvb response types are not `Clone`.
+//! Every endpoint has its own time to live: stop and POI lookups and lines hardly ever
+//! change, departure monitors and trips are only reused for a few seconds.
+
+use std::{
+    collections::HashMap,
+    fmt::Debug,
+    sync::{
+        Arc, Mutex, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use dvb::{
+    DvbResponse,
+    lines::Lines,
+    monitor::{self, DepartureMonitor},
+    point::Found,
+    route::{self, Routes},
+    trip::{self, Trip},
+};
+use rmcp::serde_json::{self, Value, json};
+use serde::{Serialize, de::DeserializeOwned};
+
+use super::{BackendFuture, Endpoint, TransitBackend, record::to_wire};
+
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+const fn default_ttl(endpoint: Endpoint) -> Duration {
+    match endpoint {
+        Endpoint::FindStops | Endpoint::FindNearbyStops | Endpoint::FindPois | Endpoint::Lines => {
+            Duration::from_secs(24 * 60 * 60)
+        }
+        Endpoint::Route => Duration::from_secs(30),
+        Endpoint::Monitor | Endpoint::Trip => Duration::from_secs(10),
+    }
+}
+
+/// Hit and miss counters of a [`CachingBackend`], published as `dvb://server/cache`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct CacheStats {
+    /// Requests answered from the cache
+    pub hits: u64,
+    /// Requests passed on upstream
+    pub misses: u64,
+    /// Responses currently kept
+    pub entries: usize,
+}
+
+struct Entry {
+    response: Value,
+    expires: Instant,
+}
+
+/// Passes calls on to another backend and reuses its responses until they expire
+pub struct CachingBackend {
+    inner: Arc<dyn TransitBackend>,
+    capacity: usize,
+    ttls: HashMap<Endpoint, Duration>,
+    entries: Mutex<HashMap<(Endpoint, String), Entry>>,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CachingBackend {
+    pub fn new(inner: Arc<dyn TransitBackend>) -> Self {
+        Self {
+            inner,
+            capacity: DEFAULT_CAPACITY,
+            ttls: HashMap::new(),
+            entries: Mutex::default(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    /// Keep at most `capacity` responses, dropping the ones closest to expiry first
+    pub fn with_capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity;
+        self
+    }
+
+    /// Override how long responses of `endpoint` are reused
+    #[cfg(test)]
+    pub fn with_ttl(mut self, endpoint: Endpoint, ttl: Duration) -> Self {
+        self.ttls.insert(endpoint, ttl);
+        self
+    }
+
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            entries: self.lock().len(),
+        }
+    }
+
+    fn ttl(&self, endpoint: Endpoint) -> Duration {
+        self.ttls
+            .get(&endpoint)
+            .copied()
+            .unwrap_or(default_ttl(endpoint))
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<(Endpoint, String), Entry>> {
+        self.entries.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lookup<T: DeserializeOwned + Debug>(
+        &self,
+        key: &(Endpoint, String),
+    ) -> Option<DvbResponse<T>> {
+        let mut entries = self.lock();
+        let entry = entries.get(key)?;
+        if entry.expires <= Instant::now() {
+            entries.remove(key);
+            return None;
+        }
+        serde_json::from_value(entry.response.clone())
+            .inspect_err(|error| tracing::warn!("dropping unreadable cache entry: {error}"))
+            .ok()
+    }
+
+    fn store<T: Serialize + Debug>(&self, key: (Endpoint, String), response: &DvbResponse<T>) {
+        let ttl = self.ttl(key.0);
+        if ttl.is_zero() || self.capacity == 0 {
+            return;
+        }
+        let response = match serde_json::to_value(response) {
+            Ok(response) => to_wire(response),
+            Err(error) => {
+                tracing::warn!("failed to cache {:?} response: {error}", key.0);
+                return;
+            }
+        };
+
+        let now = Instant::now();
+        let mut entries = self.lock();
+        if entries.len() >= self.capacity && !entries.contains_key(&key) {
+            entries.retain(|_, entry| entry.expires > now);
+        }
+        while entries.len() >= self.capacity && !entries.contains_key(&key) {
+            let Some(soonest) = entries
+                .iter()
+                .min_by_key(|(_, entry)| entry.expires)
+                .map(|(key, _)| key.clone())
+            else {
+                break;
+            };
+            entries.remove(&soonest);
+        }
+        entries.insert(
+            key,
+            Entry {
+                response,
+                expires: now + ttl,
+            },
+        );
+    }
+
+    fn cached<'a, T: Serialize + DeserializeOwned + Debug + Send + 'a>(
+        &'a self,
+        endpoint: Endpoint,
+        request: Value,
+        fetch: impl FnOnce() -> BackendFuture<'a, DvbResponse<T>>,
+    ) -> BackendFuture<'a, DvbResponse<T>> {
+        let key = (endpoint, request.to_string());
+        if let Some(response) = self.lookup(&key) {
+            self.hits.fetch_add(1, Ordering::Relaxed);
+            tracing::debug!("cache hit for {endpoint:?} {}", key.1);
+            return Box::pin(async move { Ok(response) });
+        }
+
+        self.misses.fetch_add(1, Ordering::Relaxed);
+        tracing::debug!("cache miss for {endpoint:?} {}", key.1);
+        let response = fetch();
+        Box::pin(async move {
+            let response = response.await?;
+            self.store(key, &response);
+            Ok(response)
+        })
+    }
+}
+
+impl Drop for CachingBackend {
+    fn drop(&mut self) {
+        let CacheStats { hits, misses, .. } = self.stats();
+        tracing::debug!("response cache: {hits} hits, {misses} misses");
+    }
+}
+
+impl TransitBackend for CachingBackend {
+    fn find_stops<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        let request = json!({ "query": query });
+        self.cached(Endpoint::FindStops, request, || {
+            self.inner.find_stops(query)
+        })
+    }
+
+    fn find_nearby_stops<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        let request = json!({ "query": query });
+        self.cached(Endpoint::FindNearbyStops, request, || {
+            self.inner.find_nearby_stops(query)
+        })
+    }
+
+    fn find_pois<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        let request = json!({ "query": query });
+        self.cached(Endpoint::FindPois, request, || self.inner.find_pois(query))
+    }
+
+    fn departure_monitor<'a>(
+        &'a self,
+        params: monitor::Params<'a>,
+    ) -> BackendFuture<'a, DvbResponse<DepartureMonitor>> {
+        let request = serde_json::to_value(&params).unwrap_or_default();
+        self.cached(Endpoint::Monitor, request, || {
+            self.inner.departure_monitor(params)
+        })
+    }
+
+    fn trip_details<'a>(
+        &'a self,
+        params: &'a trip::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Trip>> {
+        let request = serde_json::to_value(params).unwrap_or_default();
+        self.cached(Endpoint::Trip, request, || self.inner.trip_details(params))
+    }
+
+    fn route_details<'a>(
+        &'a self,
+        params: &'a route::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Routes>> {
+        let request = serde_json::to_value(params).unwrap_or_default();
+        self.cached(Endpoint::Route, request, || {
+            self.inner.route_details(params)
+        })
+    }
+
+    fn lines<'a>(&'a self, stop_id: &'a str) -> BackendFuture<'a, DvbResponse<Lines>> {
+        let request = json!({ "stopid": stop_id });
+        self.cached(Endpoint::Lines, request, || self.inner.lines(stop_id))
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        Some(self.stats())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::backend::fixture::cached_fixtures;
+
+    fn json<T: Serialize>(value: &T) -> Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    fn stats(hits: u64, misses: u64, entries: usize) -> CacheStats {
+        CacheStats {
+            hits,
+            misses,
+            entries,
+        }
+    }
+
+    #[tokio::test]
+    async fn repeated_requests_are_served_from_cache() {
+        let cache = cached_fixtures();
+
+        let first = cache.find_stops("Hauptbahnhof").await.unwrap();
+        let second = cache.find_stops("Hauptbahnhof").await.unwrap();
+        assert_eq!(json(&first), json(&second));
+        assert_eq!(cache.stats(), stats(1, 1, 1));
+
+        let params = || monitor::Params {
+            stopid: "33000037",
+            ..Default::default()
+        };
+        let board = cache.departure_monitor(params()).await.unwrap();
+        let cached = cache.departure_monitor(params()).await.unwrap();
+        assert_eq!(json(&board), json(&cached));
+        assert_eq!(cache.stats(), stats(2, 2, 2));
+
+        // failures are not cached
+        assert!(cache.find_stops("Atlantis").await.is_err());
+        assert!(cache.find_stops("Atlantis").await.is_err());
+        assert_eq!(cache.stats(), stats(2, 4, 2));
+    }
+
+    #[tokio::test]
+    async fn expired_responses_are_fetched_again() {
+        let cache = cached_fixtures().with_ttl(Endpoint::Lines, Duration::ZERO);
+
+        cache.lines("33000037").await.unwrap();
+        cache.lines("33000037").await.unwrap();
+        assert_eq!(cache.stats(), stats(0, 2, 0));
+    }
+
+    #[tokio::test]
+    async fn capacity_bounds_the_cache() {
+        let cache = cached_fixtures()
+            .with_capacity(2)
+            .with_ttl(Endpoint::FindPois, Duration::from_secs(1));
+
+        cache.find_pois("Zwinger").await.unwrap();
+        cache.find_stops("Postplatz").await.unwrap();
+        cache.find_stops("Albertplatz").await.unwrap();
+        assert_eq!(cache.stats().entries, 2);
+
+        // the entry closest to expiry made room
+        cache.find_stops("Postplatz").await.unwrap();
+        cache.find_pois("Zwinger").await.unwrap();
+        assert_eq!(cache.stats(), stats(1, 4, 2));
+    }
+}
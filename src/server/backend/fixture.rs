@@ -0,0 +1,190 @@
+//! Offline backend answering from canned or recorded VVO responses
+//!
+//! A fixture directory contains JSON files of the form
+//!
+//! ```json
+//! { "endpoint": "monitor", "request": { "stopid": "33000037" }, "response": { ... } }
+//! ```
+//!
+//! `response` is the raw VVO payload. Every field given in `request` has to match the
+//! outgoing request, fields left out match anything.
+//!
+//! Fixtures are used up in file name order: a request is answered by the first matching
+//! fixture that has not been used yet. Once all matching fixtures are used, the last one
+//! keeps answering. This replays a recorded session deterministically, even if the same
+//! request was made several times.
+//!
+//! Recorded fixtures also carry the time they were `recorded_at`, so a replay can run
+//! with the clock the recording started at.
+
+use std::{
+    fmt::Debug,
+    fs,
+    path::Path,
+    sync::{Mutex, PoisonError},
+};
+
+use anyhow::{Context, anyhow};
+use chrono::{DateTime, FixedOffset};
+use dvb::{
+    DvbResponse,
+    lines::Lines,
+    monitor::{self, DepartureMonitor},
+    point::Found,
+    route::{self, Routes},
+    trip::{self, Trip},
+};
+use rmcp::serde_json::{self, Value, json};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+use super::{BackendFuture, Endpoint, TransitBackend};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Fixture {
+    pub endpoint: Endpoint,
+    #[serde(default)]
+    pub request: Value,
+    pub response: Value,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub recorded_at: Option<DateTime<FixedOffset>>,
+}
+
+#[derive(Debug, Default)]
+pub struct FixtureBackend {
+    fixtures: Vec<Fixture>,
+    used: Mutex<Vec<bool>>,
+}
+
+impl FixtureBackend {
+    /// Load all `*.json` fixtures in `dir`, in file name order
+    pub fn from_dir(dir: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let dir = dir.as_ref();
+        let mut paths = fs::read_dir(dir)
+            .with_context(|| format!("failed to read fixture directory {}", dir.display()))?
+            .map(|entry| entry.map(|entry| entry.path()))
+            .collect::<Result<Vec<_>, _>>()?;
+        paths.retain(|path| path.extension().is_some_and(|ext| ext == "json"));
+        paths.sort();
+
+        let fixtures = paths
+            .iter()
+            .map(|path| {
+                let content = fs::read_to_string(path)?;
+                serde_json::from_str(&content)
+                    .with_context(|| format!("invalid fixture {}", path.display()))
+            })
+            .collect::<anyhow::Result<Vec<Fixture>>>()?;
+
+        Ok(Self {
+            used: Mutex::new(vec![false; fixtures.len()]),
+            fixtures,
+        })
+    }
+
+    /// When the first recorded fixture was recorded, `None` for canned fixtures
+    pub fn recorded_at(&self) -> Option<DateTime<FixedOffset>> {
+        self.fixtures.iter().find_map(|fixture| fixture.recorded_at)
+    }
+
+    fn respond<T: DeserializeOwned + Debug>(
+        &self,
+        endpoint: Endpoint,
+        request: Value,
+    ) -> anyhow::Result<DvbResponse<T>> {
+        let candidates = self
+            .fixtures
+            .iter()
+            .enumerate()
+            .filter(|(_, fixture)| {
+                fixture.endpoint == endpoint && matches(&fixture.request, &request)
+            })
+            .map(|(index, _)| index)
+            .collect::<Vec<_>>();
+
+        let mut used = self.used.lock().unwrap_or_else(PoisonError::into_inner);
+        let index = candidates
+            .iter()
+            .find(|&&index| !used[index])
+            .or(candidates.last())
+            .copied()
+            .ok_or_else(|| anyhow!("no fixture for {endpoint:?} {request}"))?;
+        used[index] = true;
+
+        Ok(serde_json::from_value(
+            self.fixtures[index].response.clone(),
+        )?)
+    }
+
+    fn ready<'a, T: DeserializeOwned + Debug + Send + 'a>(
+        &self,
+        endpoint: Endpoint,
+        request: impl Serialize,
+    ) -> BackendFuture<'a, DvbResponse<T>> {
+        let response = serde_json::to_value(request)
+            .map_err(Into::into)
+            .and_then(|request| self.respond(endpoint, request));
+        Box::pin(async move { response })
+    }
+}
+
+/// Whether every field of `pattern` is present and equal in `actual`
+fn matches(pattern: &Value, actual: &Value) -> bool {
+    match (pattern, actual) {
+        (Value::Object(pattern), Value::Object(actual)) => pattern
+            .iter()
+            .all(|(key, value)| matches(value, actual.get(key).unwrap_or(&Value::Null))),
+        (pattern, actual) => pattern == actual,
+    }
+}
+
+impl TransitBackend for FixtureBackend {
+    fn find_stops<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        self.ready(Endpoint::FindStops, json!({ "query": query }))
+    }
+
+    fn find_nearby_stops<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        self.ready(Endpoint::FindNearbyStops, json!({ "query": query }))
+    }
+
+    fn find_pois<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        self.ready(Endpoint::FindPois, json!({ "query": query }))
+    }
+
+    fn departure_monitor<'a>(
+        &'a self,
+        params: monitor::Params<'a>,
+    ) -> BackendFuture<'a, DvbResponse<DepartureMonitor>> {
+        self.ready(Endpoint::Monitor, params)
+    }
+
+    fn trip_details<'a>(
+        &'a self,
+        params: &'a trip::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Trip>> {
+        self.ready(Endpoint::Trip, params)
+    }
+
+    fn route_details<'a>(
+        &'a self,
+        params: &'a route::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Routes>> {
+        self.ready(Endpoint::Route, params)
+    }
+
+    fn lines<'a>(&'a self, stop_id: &'a str) -> BackendFuture<'a, DvbResponse<Lines>> {
+        self.ready(Endpoint::Lines, json!({ "stopid": stop_id }))
+    }
+}
+
+/// The canned responses in `fixtures/` the tests run against
+#[cfg(test)]
+pub fn fixtures() -> FixtureBackend {
+    FixtureBackend::from_dir(concat!(env!("CARGO_MANIFEST_DIR"), "/fixtures"))
+        .expect("fixtures load")
+}
+
+/// [`fixtures`] behind a response cache, as live responses are served
+#[cfg(test)]
+pub fn cached_fixtures() -> super::cache::CachingBackend {
+    super::cache::CachingBackend::new(std::sync::Arc::new(fixtures()))
+}
@@ -0,0 +1,287 @@
+//! Recording of upstream requests and responses for later replay
+//!
+//! Every successful upstream call is written as a [`Fixture`] file (`0001-monitor.json`, ...),
+//! so a recording directory can be replayed with [`FixtureBackend`](super::fixture::FixtureBackend).
+//! Each file notes when the call was made, which lets the replay start its clock there.
+
+use std::{
+    fmt::Debug,
+    fs, io,
+    path::PathBuf,
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use anyhow::Context;
+use chrono::{DateTime, FixedOffset};
+use dvb::{
+    DvbResponse, DvbTime,
+    lines::Lines,
+    monitor::{self, DepartureMonitor},
+    point::Found,
+    route::{self, Routes},
+    trip::{self, Trip},
+};
+use rmcp::serde_json::{self, Value, json};
+use serde::Serialize;
+
+use super::{BackendFuture, Endpoint, TransitBackend, cache::CacheStats, fixture::Fixture};
+
+/// Passes calls on to another backend and records every exchange into a directory
+pub struct RecordingBackend {
+    inner: Arc<dyn TransitBackend>,
+    dir: PathBuf,
+    next: AtomicUsize,
+}
+
+impl RecordingBackend {
+    /// Record into `dir`, appending to recordings already in there
+    pub fn new(inner: Arc<dyn TransitBackend>, dir: impl Into<PathBuf>) -> anyhow::Result<Self> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)
+            .with_context(|| format!("failed to create recording directory {}", dir.display()))?;
+        let existing = fs::read_dir(&dir)?
+            .filter_map(Result::ok)
+            .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "json"))
+            .count();
+
+        Ok(Self {
+            inner,
+            dir,
+            next: AtomicUsize::new(existing + 1),
+        })
+    }
+
+    fn record<'a, T: Serialize + Debug + Send + 'a>(
+        &'a self,
+        endpoint: Endpoint,
+        request: Value,
+        response: BackendFuture<'a, DvbResponse<T>>,
+    ) -> BackendFuture<'a, DvbResponse<T>> {
+        let recorded_at = chrono::Local::now().fixed_offset();
+        Box::pin(async move {
+            let response = response.await?;
+            if let Err(error) = self.save(endpoint, request, &response, recorded_at) {
+                tracing::warn!("failed to record {endpoint:?} response: {error}");
+            }
+            Ok(response)
+        })
+    }
+
+    fn save<T: Serialize + Debug>(
+        &self,
+        endpoint: Endpoint,
+        request: Value,
+        response: &DvbResponse<T>,
+        recorded_at: DateTime<FixedOffset>,
+    ) -> io::Result<()> {
+        let fixture = Fixture {
+            endpoint,
+            request,
+            response: to_wire(serde_json::to_value(response)?),
+            recorded_at: Some(recorded_at),
+        };
+
+        let number = self.next.fetch_add(1, Ordering::Relaxed);
+        let name = serde_json::to_value(endpoint)?;
+        let path = self.dir.join(format!(
+            "{number:04}-{}.json",
+            name.as_str().unwrap_or("call")
+        ));
+        fs::write(path, serde_json::to_vec_pretty(&fixture)?)
+    }
+}
+
+/// Turn a serialized dvb response back into the wire format of the VVO API
+///
+/// The dvb crate serializes times as RFC3339 and points as objects,
+/// but only parses `/Date(...)/` strings and `id||city|name|x|y|0||` points.
+pub(super) fn to_wire(mut response: Value) -> Value {
+    restore_times(&mut response);
+    if let Some(Value::Array(points)) = response.get_mut("Points") {
+        for point in points {
+            if let Some(wire) = point_to_wire(point) {
+                *point = Value::String(wire);
+            }
+        }
+    }
+    response
+}
+
+fn restore_times(value: &mut Value) {
+    match value {
+        Value::String(string) => {
+            if let Ok(time) = DateTime::parse_from_rfc3339(string) {
+                *string = DvbTime::from(time).to_string();
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(restore_times),
+        Value::Object(fields) => fields.values_mut().for_each(restore_times),
+        _ => {}
+    }
+}
+
+fn point_to_wire(point: &Value) -> Option<String> {
+    let field = |name: &str| point.get(name)?.as_str();
+    let coords = point.get("coords")?.as_array()?;
+    Some(format!(
+        "{}||{}|{}|{}|{}|0||",
+        field("id")?,
+        field("city")?,
+        field("name")?,
+        coords.first()?,
+        coords.get(1)?
+    ))
+}
+
+impl TransitBackend for RecordingBackend {
+    fn find_stops<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        let request = json!({ "query": query });
+        self.record(Endpoint::FindStops, request, self.inner.find_stops(query))
+    }
+
+    fn find_nearby_stops<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        let request = json!({ "query": query });
+        let response = self.inner.find_nearby_stops(query);
+        self.record(Endpoint::FindNearbyStops, request, response)
+    }
+
+    fn find_pois<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        let request = json!({ "query": query });
+        self.record(Endpoint::FindPois, request, self.inner.find_pois(query))
+    }
+
+    fn departure_monitor<'a>(
+        &'a self,
+        params: monitor::Params<'a>,
+    ) -> BackendFuture<'a, DvbResponse<DepartureMonitor>> {
+        let request = serde_json::to_value(&params).unwrap_or_default();
+        let response = self.inner.departure_monitor(params);
+        self.record(Endpoint::Monitor, request, response)
+    }
+
+    fn trip_details<'a>(
+        &'a self,
+        params: &'a trip::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Trip>> {
+        let request = serde_json::to_value(params).unwrap_or_default();
+        self.record(Endpoint::Trip, request, self.inner.trip_details(params))
+    }
+
+    fn route_details<'a>(
+        &'a self,
+        params: &'a route::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Routes>> {
+        let request = serde_json::to_value(params).unwrap_or_default();
+        self.record(Endpoint::Route, request, self.inner.route_details(params))
+    }
+
+    fn lines<'a>(&'a self, stop_id: &'a str) -> BackendFuture<'a, DvbResponse<Lines>> {
+        let request = json!({ "stopid": stop_id });
+        self.record(Endpoint::Lines, request, self.inner.lines(stop_id))
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::backend::fixture::{FixtureBackend, fixtures};
+
+    fn scratch_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dvb-mcp-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn json<T: Serialize>(value: &T) -> Value {
+        serde_json::to_value(value).unwrap()
+    }
+
+    #[tokio::test]
+    async fn recordings_replay_identically() {
+        let dir = scratch_dir("record");
+        let recorder = RecordingBackend::new(Arc::new(fixtures()), &dir).unwrap();
+        let started = chrono::Local::now().fixed_offset();
+
+        let stops = recorder.find_stops("Hauptbahnhof").await.unwrap();
+        let monitor = recorder
+            .departure_monitor(monitor::Params {
+                stopid: "33000037",
+                limit: Some(10),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        let route_params = route::Params {
+            origin: "33000028",
+            destination: "33000013",
+            time: DvbTime::now(),
+            isarrivaltime: false,
+            shorttermchanges: true,
+            format: "json",
+            via: None,
+            mobility_settings: None,
+            standard_settings: None,
+        };
+        let routes = recorder.route_details(&route_params).await.unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 3);
+
+        let replay = FixtureBackend::from_dir(&dir).unwrap();
+        let recorded_at = replay.recorded_at().expect("recordings are timestamped");
+        assert!(recorded_at >= started && recorded_at <= chrono::Local::now());
+        assert_eq!(
+            json(&replay.find_stops("Hauptbahnhof").await.unwrap()),
+            json(&stops)
+        );
+        let replayed_monitor = replay
+            .departure_monitor(monitor::Params {
+                stopid: "33000037",
+                limit: Some(10),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+        assert_eq!(json(&replayed_monitor), json(&monitor));
+        assert_eq!(
+            json(&replay.route_details(&route_params).await.unwrap()),
+            json(&routes)
+        );
+
+        // only recorded requests can be replayed
+        assert!(replay.find_stops("Postplatz").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn replay_follows_recording_order() {
+        let dir = scratch_dir("replay-order");
+        fs::create_dir_all(&dir).unwrap();
+        for (number, name) in [(1, "Postplatz"), (2, "Pirnaischer Platz")] {
+            let fixture = json!({
+                "endpoint": "monitor",
+                "request": { "stopid": "33000037" },
+                "response": { "Status": { "Code": "Ok" }, "Name": name },
+            });
+            fs::write(
+                dir.join(format!("{number:04}-monitor.json")),
+                fixture.to_string(),
+            )
+            .unwrap();
+        }
+
+        let replay = FixtureBackend::from_dir(&dir).unwrap();
+        let params = || monitor::Params {
+            stopid: "33000037",
+            ..Default::default()
+        };
+        for expected in ["Postplatz", "Pirnaischer Platz", "Pirnaischer Platz"] {
+            let board = replay.departure_monitor(params()).await.unwrap();
+            assert_eq!(board.name.as_deref(), Some(expected));
+        }
+    }
+}
@@ -0,0 +1,127 @@
+//! Upstream calls made on behalf of a single tool call
+//!
+//! Every finished call is reported to the client as MCP progress if the request carries a
+//! progress token. Once the client cancels the request, calls still in flight are dropped
+//! and new ones fail right away, so a tool gives up at its next upstream call.
+
+use std::sync::atomic::{AtomicU32, Ordering};
+
+use anyhow::anyhow;
+use dvb::{
+    DvbResponse,
+    lines::Lines,
+    monitor::{self, DepartureMonitor},
+    point::Found,
+    route::{self, Routes},
+    trip::{self, Trip},
+};
+use rmcp::{
+    model::{ProgressNotificationParam, ProgressToken},
+    service::{Peer, RequestContext, RoleServer},
+};
+use tokio_util::sync::CancellationToken;
+
+use super::{BackendFuture, TransitBackend, cache::CacheStats};
+
+/// Passes calls on to the server's backend for the duration of one request
+pub struct TrackedBackend<'b> {
+    inner: &'b dyn TransitBackend,
+    peer: Peer<RoleServer>,
+    progress_token: Option<ProgressToken>,
+    ct: CancellationToken,
+    finished: AtomicU32,
+}
+
+impl<'b> TrackedBackend<'b> {
+    pub fn new(inner: &'b dyn TransitBackend, context: &RequestContext<RoleServer>) -> Self {
+        Self {
+            inner,
+            peer: context.peer.clone(),
+            progress_token: context.meta.get_progress_token(),
+            ct: context.ct.clone(),
+            finished: AtomicU32::new(0),
+        }
+    }
+
+    /// The client that made the request
+    pub fn peer(&self) -> &Peer<RoleServer> {
+        &self.peer
+    }
+
+    fn track<'a, T: Send + 'a>(
+        &'a self,
+        done: String,
+        call: BackendFuture<'a, T>,
+    ) -> BackendFuture<'a, T> {
+        Box::pin(async move {
+            let response = tokio::select! {
+                biased;
+                _ = self.ct.cancelled() => return Err(anyhow!("request cancelled by the client")),
+                response = call => response?,
+            };
+
+            if let Some(token) = &self.progress_token {
+                let finished = self.finished.fetch_add(1, Ordering::Relaxed) + 1;
+                let progress = ProgressNotificationParam::new(token.clone(), finished.into())
+                    .with_message(done);
+                if let Err(error) = self.peer.notify_progress(progress).await {
+                    tracing::debug!("failed to report progress: {error}");
+                }
+            }
+            Ok(response)
+        })
+    }
+}
+
+impl TransitBackend for TrackedBackend<'_> {
+    fn find_stops<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        let done = format!("found stops matching {query:?}");
+        self.track(done, self.inner.find_stops(query))
+    }
+
+    fn find_nearby_stops<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        let done = format!("found stops near {query:?}");
+        self.track(done, self.inner.find_nearby_stops(query))
+    }
+
+    fn find_pois<'a>(&'a self, query: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        let done = format!("found places matching {query:?}");
+        self.track(done, self.inner.find_pois(query))
+    }
+
+    fn departure_monitor<'a>(
+        &'a self,
+        params: monitor::Params<'a>,
+    ) -> BackendFuture<'a, DvbResponse<DepartureMonitor>> {
+        let done = format!("fetched departures at {}", params.stopid);
+        self.track(done, self.inner.departure_monitor(params))
+    }
+
+    fn trip_details<'a>(
+        &'a self,
+        params: &'a trip::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Trip>> {
+        let done = format!("fetched trip {}", params.tripid);
+        self.track(done, self.inner.trip_details(params))
+    }
+
+    fn route_details<'a>(
+        &'a self,
+        params: &'a route::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Routes>> {
+        let done = format!(
+            "fetched routes from {} to {}",
+            params.origin, params.destination
+        );
+        self.track(done, self.inner.route_details(params))
+    }
+
+    fn lines<'a>(&'a self, stop_id: &'a str) -> BackendFuture<'a, DvbResponse<Lines>> {
+        let done = format!("fetched lines at {stop_id}");
+        self.track(done, self.inner.lines(stop_id))
+    }
+
+    fn cache_stats(&self) -> Option<CacheStats> {
+        self.inner.cache_stats()
+    }
+}
@@ -0,0 +1,150 @@
+//! Departure boards narrowed down to some lines, directions or platforms
+//!
+//! The VVO departure monitor only filters by mode of transport, everything else is filtered
+//! here. Since that can leave fewer departures than asked for, the board is fetched again
+//! with a larger limit until enough departures match or the stop has no more to offer.
+//!
+//! Boards are also published as `dvb://departures/{stop_id}` resources, see [`BoardResource`].
+
+use dvb::{
+    DvbResponse,
+    monitor::{self, DepartureMonitor},
+};
+
+use crate::server::{
+    TransitBackend,
+    args::{BoardOptions, DepartureFilter},
+};
+
+/// Departures returned when no limit is given
+pub const DEFAULT_LIMIT: u32 = 10;
+
+/// Largest board fetched to fill up a filtered one
+const MAX_FETCH: u32 = 200;
+
+/// Departures listed by a board resource
+const RESOURCE_LIMIT: u32 = 10;
+
+/// The board behind a `dvb://departures/{stop_id}` uri, with its optional query parameters
+#[derive(Debug)]
+pub struct BoardResource {
+    pub stop_id: String,
+    pub options: BoardOptions,
+}
+
+impl BoardResource {
+    /// `None` if `uri` is no departure board, an error if its query is invalid
+    pub fn parse(uri: &str) -> Option<Result<Self, String>> {
+        let path = uri.strip_prefix("dvb://departures/")?;
+        let (stop_id, query) = path.split_once('?').unwrap_or((path, ""));
+        Some(BoardOptions::from_query(query).map(|options| Self {
+            stop_id: stop_id.into(),
+            options,
+        }))
+    }
+
+    pub async fn fetch(
+        &self,
+        backend: &dyn TransitBackend,
+    ) -> anyhow::Result<DvbResponse<DepartureMonitor>> {
+        let time = self.options.vvo_time();
+        let params = monitor::Params {
+            stopid: &self.stop_id,
+            mot: None,
+            limit: Some(RESOURCE_LIMIT),
+            time: time.as_deref(),
+            isarrival: self.options.is_arrival,
+            shorttermchanges: self.options.shorttermchanges,
+        };
+        backend.departure_monitor(params).await
+    }
+}
+
+/// Fetch the board for `params`, keeping the first `params.limit` departures `filter` matches
+pub async fn departures(
+    backend: &dyn TransitBackend,
+    params: monitor::Params<'_>,
+    filter: &DepartureFilter,
+) -> anyhow::Result<DepartureMonitor> {
+    if filter.is_empty() {
+        return Ok(backend.departure_monitor(params).await?.into_inner());
+    }
+
+    let wanted = params.limit.unwrap_or(DEFAULT_LIMIT) as usize;
+    let mut fetch = params.limit.unwrap_or(DEFAULT_LIMIT).max(1);
+    loop {
+        let params = monitor::Params {
+            limit: Some(fetch),
+            ..params.clone()
+        };
+        let mut board = backend.departure_monitor(params).await?.into_inner();
+        let departures = board.departures.get_or_insert_default();
+        let exhausted = departures.len() < fetch as usize || fetch >= MAX_FETCH;
+        departures.retain(|departure| filter.matches(departure));
+
+        if departures.len() >= wanted || exhausted {
+            departures.truncate(wanted);
+            return Ok(board);
+        }
+        tracing::debug!(
+            "only {} of {wanted} departures match, fetching more",
+            departures.len()
+        );
+        fetch = (fetch * 4).min(MAX_FETCH);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::backend::fixture::cached_fixtures;
+
+    fn params(limit: u32) -> monitor::Params<'static> {
+        monitor::Params {
+            stopid: "33000037",
+            limit: Some(limit),
+            ..Default::default()
+        }
+    }
+
+    fn line_11() -> DepartureFilter {
+        DepartureFilter {
+            lines: Some(vec!["11".into()]),
+            ..Default::default()
+        }
+    }
+
+    #[tokio::test]
+    async fn enough_matches_need_a_single_fetch() {
+        let backend = cached_fixtures();
+        let board = departures(&backend, params(1), &line_11()).await.unwrap();
+        let departures = board.departures.unwrap();
+        assert_eq!(departures.len(), 1);
+        assert_eq!(departures[0].direction, "Bühlau");
+        assert_eq!(backend.stats().misses, 1);
+    }
+
+    #[tokio::test]
+    async fn boards_are_fetched_again_until_the_stop_runs_out() {
+        let backend = cached_fixtures();
+        let board = departures(&backend, params(5), &line_11()).await.unwrap();
+        // the stop only has two departures of line 11, more than five were fetched on the retry
+        assert_eq!(board.departures.unwrap().len(), 2);
+        assert_eq!(backend.stats().misses, 2);
+    }
+
+    #[tokio::test]
+    async fn filters_combine() {
+        let filter = DepartureFilter {
+            lines: Some(vec!["11".into()]),
+            direction: Some("zschert".into()),
+            platform: Some("3".into()),
+        };
+        let board = departures(&cached_fixtures(), params(10), &filter)
+            .await
+            .unwrap();
+        let departures = board.departures.unwrap();
+        assert_eq!(departures.len(), 1);
+        assert_eq!(departures[0].direction, "Zschertnitz");
+    }
+}
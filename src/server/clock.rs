@@ -0,0 +1,31 @@
+//! Where the server gets the current time from
+
+use std::sync::Arc;
+
+use chrono::{DateTime, FixedOffset};
+
+/// Source of "now", so time dependent answers can be tested against fixed fixtures
+#[derive(Clone)]
+pub struct Clock(Arc<dyn Fn() -> DateTime<FixedOffset> + Send + Sync>);
+
+impl Clock {
+    /// The local system time
+    pub fn system() -> Self {
+        Self(Arc::new(|| chrono::Local::now().fixed_offset()))
+    }
+
+    /// Always `time`, e.g. the start of a replayed recording
+    pub fn fixed(time: DateTime<FixedOffset>) -> Self {
+        Self(Arc::new(move || time))
+    }
+
+    pub fn now(&self) -> DateTime<FixedOffset> {
+        (self.0)()
+    }
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::system()
+    }
+}
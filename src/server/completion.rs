@@ -0,0 +1,122 @@
+//! Completion of stop names in prompt arguments and stop ids in prompt arguments and resource uris
+//!
+//! Suggestions start with the places saved in the user context, followed by the stops the
+//! VVO PointFinder knows for what was typed so far. Stop ids are looked up by the stop's name,
+//! so typing "Bahnhof" offers "33000016" for Bahnhof Neustadt. Lookups wait until the user pauses:
+//! a request overtaken by a newer one of the same session within [`DEBOUNCE`] is answered
+//! from the saved places alone. Repeated lookups are answered by the backend's response cache.
+
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use dvb::poi::PoiType;
+use rmcp::model::CompletionInfo;
+
+use crate::server::{TransitBackend, usercontext::SavedContext};
+
+/// Time to wait for the next keystroke before looking stops up
+pub const DEBOUNCE: Duration = Duration::from_millis(150);
+
+/// Shortest input worth looking up
+const MIN_QUERY_LEN: usize = 2;
+
+/// Prompt arguments that take a stop name
+pub const STOP_NAME_ARGUMENTS: &[&str] = &["station", "origin", "destination"];
+
+/// What an argument is completed to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Completing {
+    /// A stop name as shown to the user, e.g. "Bahnhof Neustadt"
+    StopName,
+    /// The id of a stop looked up by name, e.g. "33000016" for "Bahnhof Neustadt"
+    StopId,
+}
+
+/// Completion state of one session
+#[derive(Debug, Default)]
+pub struct Completions {
+    latest: AtomicU64,
+}
+
+impl Completions {
+    /// Suggestions for `typed`, saved places first
+    pub async fn complete(
+        &self,
+        backend: &dyn TransitBackend,
+        saved: &SavedContext,
+        completing: Completing,
+        typed: &str,
+    ) -> CompletionInfo {
+        let typed = typed.trim();
+        let mut values = saved_places(saved, completing, typed);
+
+        let ticket = self.latest.fetch_add(1, Ordering::Relaxed) + 1;
+        if typed.chars().count() >= MIN_QUERY_LEN && !typed.bytes().all(|b| b.is_ascii_digit()) {
+            tokio::time::sleep(DEBOUNCE).await;
+            if self.latest.load(Ordering::Relaxed) == ticket {
+                values.extend(lookup(backend, completing, typed).await);
+            }
+        }
+
+        let mut seen = std::collections::HashSet::new();
+        values.retain(|value| seen.insert(value.clone()));
+        values.truncate(CompletionInfo::MAX_VALUES);
+        CompletionInfo::with_all_values(values).expect("at most MAX_VALUES completions")
+    }
+}
+
+fn saved_places(saved: &SavedContext, completing: Completing, typed: &str) -> Vec<String> {
+    let typed = typed.to_lowercase();
+    [&saved.location, &saved.origin, &saved.destination]
+        .into_iter()
+        .flatten()
+        .filter(|place| match completing {
+            Completing::StopName => place.to_lowercase().contains(&typed),
+            Completing::StopId => {
+                place.bytes().all(|b| b.is_ascii_digit()) && place.starts_with(&typed)
+            }
+        })
+        .cloned()
+        .collect()
+}
+
+async fn lookup(backend: &dyn TransitBackend, completing: Completing, typed: &str) -> Vec<String> {
+    let found = match backend.find_stops(typed).await {
+        Ok(found) => found.into_inner(),
+        Err(error) => {
+            tracing::debug!("no stops to complete {typed:?}: {error}");
+            return Vec::new();
+        }
+    };
+    found
+        .points
+        .into_iter()
+        .filter(|point| point.r#type == PoiType::Stop)
+        .map(|point| match completing {
+            Completing::StopName => point.name,
+            Completing::StopId => point.id,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn saved_places_match_what_was_typed() {
+        let saved = SavedContext {
+            origin: Some("33000028".into()),
+            location: Some("Bahnhof Neustadt".into()),
+            ..Default::default()
+        };
+
+        let names = saved_places(&saved, Completing::StopName, "bahnhof");
+        assert_eq!(names, ["Bahnhof Neustadt"]);
+        let ids = saved_places(&saved, Completing::StopId, "3300");
+        assert_eq!(ids, ["33000028"]);
+        assert!(saved_places(&saved, Completing::StopId, "Bahnhof").is_empty());
+    }
+}
@@ -0,0 +1,167 @@
+//! Opt-in on-disk persistence of the saved user context
+
+use rmcp::serde_json;
+use std::{
+    fs, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use crate::server::usercontext::SavedContext;
+
+const APP_DIR: &str = "dvb-mcp";
+const FILE_NAME: &str = "context.json";
+
+/// JSON file holding the saved context, survives restarts of the server
+#[derive(Debug)]
+pub struct ContextFile {
+    path: PathBuf,
+    write_lock: Mutex<()>,
+}
+
+impl ContextFile {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            write_lock: Mutex::new(()),
+        }
+    }
+
+    /// `$XDG_DATA_HOME/dvb-mcp/context.json`, falling back to `~/.local/share`
+    pub fn default_path() -> Option<PathBuf> {
+        let data_home = std::env::var_os("XDG_DATA_HOME")
+            .map(PathBuf::from)
+            .filter(|dir| dir.is_absolute())
+            .or_else(|| {
+                std::env::var_os("HOME").map(|home| PathBuf::from(home).join(".local/share"))
+            })?;
+        Some(data_home.join(APP_DIR).join(FILE_NAME))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Read the saved context
+    ///
+    /// A missing file yields an empty context. An unreadable file is moved aside
+    /// (`context.json.corrupt`) so the next save does not silently overwrite it.
+    pub fn load(&self) -> SavedContext {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(error) if error.kind() == io::ErrorKind::NotFound => {
+                return SavedContext::default();
+            }
+            Err(error) => {
+                tracing::warn!("failed to read {}: {error}", self.path.display());
+                return SavedContext::default();
+            }
+        };
+
+        match serde_json::from_str(&content) {
+            Ok(saved) => saved,
+            Err(error) => {
+                let backup = self.path.with_extension("json.corrupt");
+                tracing::warn!(
+                    "ignoring corrupt context file {} ({error}), moving it to {}",
+                    self.path.display(),
+                    backup.display()
+                );
+                if let Err(error) = fs::rename(&self.path, &backup) {
+                    tracing::warn!("failed to move corrupt context file: {error}");
+                }
+                SavedContext::default()
+            }
+        }
+    }
+
+    /// Write the saved context, replacing the file atomically
+    pub fn save(&self, saved: &SavedContext) -> io::Result<()> {
+        let _guard = self.write_lock.lock().unwrap_or_else(|e| e.into_inner());
+
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(saved)?)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{
+        UserContextStore,
+        args::{Entrance, MaxChanges, MobilityOptions, MobilityRestriction, Mot, RoutePreferences},
+    };
+
+    fn scratch_file(name: &str) -> ContextFile {
+        let dir = std::env::temp_dir().join(format!("dvb-mcp-{}-{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        ContextFile::new(dir.join(FILE_NAME))
+    }
+
+    #[test]
+    fn missing_file_is_empty_context() {
+        let file = scratch_file("missing");
+        assert_eq!(file.load(), SavedContext::default());
+    }
+
+    #[test]
+    fn saved_context_survives_reload() {
+        let file = scratch_file("roundtrip");
+        let saved = SavedContext {
+            origin: Some("Hauptbahnhof".into()),
+            location: None,
+            destination: Some("Albertplatz".into()),
+            mobility: Some(MobilityOptions {
+                restriction: MobilityRestriction::High,
+                solid_stairs: None,
+                escalators: None,
+                least_change: None,
+                entrance: Some(Entrance::NoStep),
+            }),
+            preferences: Some(RoutePreferences {
+                max_changes: Some(MaxChanges::One),
+                walking_speed: None,
+                footpath_to_stop: Some(10),
+                include_alternative_stops: None,
+                exclude_mot: Some(vec![Mot::Ferry]),
+            }),
+        };
+        file.save(&saved).unwrap();
+
+        assert_eq!(ContextFile::new(file.path()).load(), saved);
+    }
+
+    #[test]
+    fn persistent_store_saves_updates_and_seeds_new_sessions() {
+        let file = scratch_file("store");
+        let path = file.path().to_owned();
+        let store = UserContextStore::persistent(file);
+
+        let first = store.open_session();
+        first.update_context(|saved| saved.location = Some("Postplatz".into()));
+        drop(first);
+
+        // as after a restart
+        let restarted = UserContextStore::persistent(ContextFile::new(&path));
+        let session = restarted.open_session();
+        assert_eq!(session.context().location.as_deref(), Some("Postplatz"));
+
+        session.update_context(|saved| *saved = Default::default());
+        assert_eq!(ContextFile::new(&path).load(), SavedContext::default());
+    }
+
+    #[test]
+    fn corrupt_file_is_moved_aside() {
+        let file = scratch_file("corrupt");
+        fs::create_dir_all(file.path().parent().unwrap()).unwrap();
+        fs::write(file.path(), "{ not json").unwrap();
+
+        assert_eq!(file.load(), SavedContext::default());
+        assert!(!file.path().exists());
+        assert!(file.path().with_extension("json.corrupt").exists());
+    }
+}
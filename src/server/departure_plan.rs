@@ -0,0 +1,111 @@
+//! When to leave to arrive in time, published as the output schema of `plan_departure`
+//!
+//! Connections are planned backwards from the time the user wants to be there, less a buffer
+//! for finding the entrance, a late tram and the like. Of the connections arriving in time that
+//! are neither cancelled nor gone already, the one leaving last is suggested, together with the
+//! one before it for a little more margin.
+
+use chrono::{DateTime, Duration, FixedOffset};
+use dvb::route::Route;
+use rmcp::schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::server::{
+    args::DetailLevel,
+    itinerary::{self, Leg},
+    summary::RouteSummary,
+    usercontext::ContextValue,
+};
+
+/// Minutes to arrive early unless the user asks for something else
+pub const DEFAULT_BUFFER_MINUTES: u32 = 5;
+
+/// Result of `plan_departure`
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct DeparturePlan {
+    pub from: String,
+    pub to: String,
+    /// When the user wants to be at the destination
+    pub arrive_by: DateTime<FixedOffset>,
+    /// Minutes the connections arrive before `arrive_by` at the latest
+    pub buffer_minutes: u32,
+    /// The latest connection that still gets there in time
+    pub connection: PlannedConnection,
+    /// The connection before it, leaving earlier
+    pub fallback: Option<PlannedConnection>,
+    /// Request arguments that were taken from the saved user context
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub from_context: Vec<ContextValue>,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct PlannedConnection {
+    /// When to leave the origin, walks to the first stop included
+    pub leave_at: DateTime<FixedOffset>,
+    /// Minutes from now until `leave_at`
+    pub leave_in_minutes: i64,
+    /// Arrival at the destination, walks from the last stop included
+    pub arrive_at: DateTime<FixedOffset>,
+    /// Minutes between `arrive_at` and `arrive_by`
+    pub spare_minutes: i64,
+    pub route: RouteSummary,
+}
+
+impl DeparturePlan {
+    /// The latest connection of `leg` arriving `buffer_minutes` before `arrive_by`
+    ///
+    /// `None` if no connection arrives in time or they all leave before `now`.
+    pub fn new(
+        leg: &Leg,
+        arrive_by: DateTime<FixedOffset>,
+        buffer_minutes: u32,
+        now: DateTime<FixedOffset>,
+        from_context: Vec<ContextValue>,
+    ) -> Option<Self> {
+        let latest_arrival = arrive_by - Duration::minutes(buffer_minutes.into());
+        let mut candidates = leg
+            .routes
+            .routes
+            .iter()
+            .filter(|route| !route.route_cancelled.unwrap_or(false))
+            .filter_map(|route| {
+                let leave_at = itinerary::departure(route)?;
+                let arrive_at = itinerary::arrival(route)?;
+                (leave_at >= now && arrive_at <= latest_arrival)
+                    .then_some((route, leave_at, arrive_at))
+            })
+            .collect::<Vec<_>>();
+        candidates.sort_by_key(|&(_, leave_at, _)| std::cmp::Reverse(leave_at));
+
+        let mut planned = candidates.into_iter().map(|(route, leave_at, arrive_at)| {
+            PlannedConnection::new(route, leave_at, arrive_at, arrive_by, now)
+        });
+        Some(Self {
+            from: leg.from.clone(),
+            to: leg.to.clone(),
+            arrive_by,
+            buffer_minutes,
+            connection: planned.next()?,
+            fallback: planned.next(),
+            from_context,
+        })
+    }
+}
+
+impl PlannedConnection {
+    fn new(
+        route: &Route,
+        leave_at: DateTime<FixedOffset>,
+        arrive_at: DateTime<FixedOffset>,
+        arrive_by: DateTime<FixedOffset>,
+        now: DateTime<FixedOffset>,
+    ) -> Self {
+        Self {
+            leave_at,
+            leave_in_minutes: (leave_at - now).num_minutes(),
+            arrive_at,
+            spare_minutes: (arrive_by - arrive_at).num_minutes(),
+            route: RouteSummary::new(route, DetailLevel::Legs),
+        }
+    }
+}
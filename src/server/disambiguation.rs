@@ -0,0 +1,252 @@
+//! Resolving free text stop names to a single stop, asking the user when in doubt
+
+use anyhow::anyhow;
+use dvb::{poi::PoiType, point::Point};
+use rmcp::{
+    Peer, RoleServer,
+    model::{CreateElicitationRequestParams, ElicitationAction, ElicitationSchema, EnumSchema},
+    service::ElicitationMode,
+};
+use serde::Serialize;
+
+use crate::server::TransitBackend;
+
+/// Most candidates offered to the user at once
+const MAX_CANDIDATES: usize = 6;
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StopCandidate {
+    pub stop_id: String,
+    pub name: String,
+    pub city: String,
+}
+
+impl From<&Point> for StopCandidate {
+    fn from(point: &Point) -> Self {
+        Self {
+            stop_id: point.id.clone(),
+            name: point.name.clone(),
+            city: point.city.clone(),
+        }
+    }
+}
+
+impl StopCandidate {
+    fn title(&self) -> String {
+        if self.city.is_empty() {
+            self.name.clone()
+        } else {
+            format!("{} ({})", self.name, self.city)
+        }
+    }
+}
+
+/// Returned instead of a stop when the user could not be asked which one they meant
+#[derive(Debug, Serialize)]
+pub struct AmbiguousStop {
+    pub status: &'static str,
+    /// Which argument of the request was ambiguous
+    pub field: String,
+    pub query: String,
+    pub candidates: Vec<StopCandidate>,
+    pub message: String,
+}
+
+impl AmbiguousStop {
+    fn new(field: &str, query: &str, candidates: Vec<StopCandidate>) -> Self {
+        Self {
+            status: "ambiguous",
+            field: field.to_owned(),
+            query: query.to_owned(),
+            message: format!(
+                "Several stops match {query:?}. Ask the user which one they mean and call again with its stop_id."
+            ),
+            candidates,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum StopResolution {
+    Resolved(StopCandidate),
+    Ambiguous(AmbiguousStop),
+}
+
+/// Look up `query`, eliciting a choice from the user if several stops match
+///
+/// `field` names the tool argument `query` came from, it is reported back in [`AmbiguousStop`].
+/// Stop ids and the `coord:` points of GPS positions are taken as they are.
+pub async fn resolve_stop(
+    backend: &dyn TransitBackend,
+    peer: &Peer<RoleServer>,
+    field: &str,
+    query: &str,
+) -> anyhow::Result<StopResolution> {
+    if is_stop_id(query) {
+        return Ok(StopResolution::Resolved(StopCandidate {
+            stop_id: query.to_owned(),
+            name: query.to_owned(),
+            city: String::new(),
+        }));
+    }
+
+    let found = backend.find_stops(query).await?;
+    let mut candidates = plausible_candidates(&found.point_status, &found.points, query);
+    match candidates.len() {
+        0 => return Err(anyhow!("no search results for {query:?}")),
+        1 => return Ok(StopResolution::Resolved(candidates.remove(0))),
+        _ => {}
+    }
+
+    if !peer
+        .supported_elicitation_modes()
+        .contains(&ElicitationMode::Form)
+    {
+        return Ok(StopResolution::Ambiguous(AmbiguousStop::new(
+            field, query, candidates,
+        )));
+    }
+
+    match elicit_choice(peer, query, &candidates).await {
+        Some(stop_id) => {
+            let chosen = candidates
+                .into_iter()
+                .find(|candidate| candidate.stop_id == stop_id)
+                .ok_or_else(|| anyhow!("user picked an unknown stop {stop_id:?}"))?;
+            Ok(StopResolution::Resolved(chosen))
+        }
+        None => Ok(StopResolution::Ambiguous(AmbiguousStop::new(
+            field, query, candidates,
+        ))),
+    }
+}
+
+/// VVO stop ids are plain numbers like `33000037`, points like `coord:4621644:5657516`
+fn is_stop_id(query: &str) -> bool {
+    query.starts_with("coord:")
+        || query.len() >= 5 && query.bytes().all(|byte| byte.is_ascii_digit())
+}
+
+/// The stops worth asking about, a single one if the lookup is unambiguous
+fn plausible_candidates(status: &str, points: &[Point], query: &str) -> Vec<StopCandidate> {
+    let stops = points
+        .iter()
+        .filter(|point| point.r#type == PoiType::Stop)
+        .collect::<Vec<_>>();
+    let Some(first) = stops.first() else {
+        return points
+            .first()
+            .map(StopCandidate::from)
+            .into_iter()
+            .collect();
+    };
+    if status == "Identified" {
+        return vec![StopCandidate::from(*first)];
+    }
+
+    let exact = stops
+        .iter()
+        .filter(|point| point.name.eq_ignore_ascii_case(query.trim()))
+        .collect::<Vec<_>>();
+    if let [exact] = exact.as_slice() {
+        return vec![StopCandidate::from(**exact)];
+    }
+
+    let mut candidates: Vec<StopCandidate> = Vec::new();
+    for point in stops {
+        if candidates.len() == MAX_CANDIDATES {
+            break;
+        }
+        if !candidates.iter().any(|known| known.stop_id == point.id) {
+            candidates.push(point.into());
+        }
+    }
+    candidates
+}
+
+/// Ask the user to pick one of `candidates`, `None` if they did not
+async fn elicit_choice(
+    peer: &Peer<RoleServer>,
+    query: &str,
+    candidates: &[StopCandidate],
+) -> Option<String> {
+    let ids = candidates.iter().map(|c| c.stop_id.clone()).collect();
+    let titles = candidates.iter().map(StopCandidate::title).collect();
+    let choice = EnumSchema::builder(ids)
+        .enum_titles(titles)
+        .ok()?
+        .title("Stop")
+        .build();
+    let schema = ElicitationSchema::builder()
+        .required_enum_schema("stop_id", choice)
+        .build()
+        .ok()?;
+
+    let response = peer
+        .create_elicitation(CreateElicitationRequestParams::FormElicitationParams {
+            meta: None,
+            message: format!("Which stop do you mean by {query:?}?"),
+            requested_schema: schema,
+        })
+        .await
+        .inspect_err(|error| tracing::warn!("stop elicitation failed: {error}"))
+        .ok()?;
+
+    match response.action {
+        ElicitationAction::Accept => response
+            .content?
+            .get("stop_id")?
+            .as_str()
+            .map(str::to_owned),
+        ElicitationAction::Decline | ElicitationAction::Cancel => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn points(lines: &[&str]) -> Vec<Point> {
+        lines.iter().map(|s| Point::from_str(s).unwrap()).collect()
+    }
+
+    #[test]
+    fn identified_points_are_not_ambiguous() {
+        let points = points(&[
+            "33000037|||Postplatz|5660218|4621212|0||",
+            "33000016|||Bahnhof Neustadt|5661480|4622030|0||",
+        ]);
+        let candidates = plausible_candidates("Identified", &points, "Post");
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].stop_id, "33000037");
+    }
+
+    #[test]
+    fn exact_name_match_wins() {
+        let points = points(&[
+            "33000028|||Hauptbahnhof|5657516|4621644|0||",
+            "33000032|||Hauptbahnhof Nord|5657679|4621791|0||",
+        ]);
+        let candidates = plausible_candidates("List", &points, "hauptbahnhof ");
+        assert_eq!(candidates.len(), 1);
+        assert_eq!(candidates[0].name, "Hauptbahnhof");
+    }
+
+    #[test]
+    fn lists_are_deduplicated_and_bounded() {
+        let mut lines = vec!["33000016|||Bahnhof Neustadt|5661480|4622030|0||"; 2];
+        lines.extend([
+            "33000030|||Bahnhof Mitte|5659556|4620517|0||",
+            "33000001|||A|1|1|0||",
+            "33000002|||B|1|1|0||",
+            "33000003|||C|1|1|0||",
+            "33000004|||D|1|1|0||",
+            "33000005|||E|1|1|0||",
+        ]);
+        let candidates = plausible_candidates("List", &points(&lines), "Bahnhof");
+        assert_eq!(candidates.len(), MAX_CANDIDATES);
+        assert_eq!(candidates[0].name, "Bahnhof Neustadt");
+        assert_eq!(candidates[1].name, "Bahnhof Mitte");
+    }
+}
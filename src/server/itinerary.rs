@@ -0,0 +1,237 @@
+//! Routes through several via stops, planned leg by leg
+//!
+//! The VVO API takes at most one via stop and no dwell time, so anything beyond that is
+//! queried as separate legs. Each leg starts when the earliest connection of the previous
+//! leg arrives plus the dwell time at the via stop. Arrival-based queries are planned
+//! backwards from the destination the same way.
+
+use anyhow::anyhow;
+use chrono::{DateTime, Duration, FixedOffset};
+use dvb::{
+    DvbTime,
+    route::{self, MobilitySettings, Route, Routes, StandardSettings},
+};
+
+use crate::server::TransitBackend;
+
+/// A resolved stop along the route
+#[derive(Debug, Clone)]
+pub struct Waypoint {
+    pub name: String,
+    pub stop_id: String,
+    /// Minutes to stay at this stop before continuing
+    pub dwell_minutes: u32,
+}
+
+/// Settings shared by all legs of a route
+pub struct RouteOptions {
+    pub isarrivaltime: bool,
+    pub shorttermchanges: bool,
+    pub mobility: Option<MobilitySettings>,
+    pub standard: Option<StandardSettings>,
+}
+
+impl RouteOptions {
+    fn params<'a>(
+        &'a self,
+        from: &'a Waypoint,
+        to: &'a Waypoint,
+        time: DvbTime,
+        via: Option<&'a str>,
+    ) -> route::Params<'a> {
+        route::Params {
+            origin: &from.stop_id,
+            destination: &to.stop_id,
+            time,
+            isarrivaltime: self.isarrivaltime,
+            shorttermchanges: self.shorttermchanges,
+            format: "json",
+            via,
+            mobility_settings: self.mobility.clone(),
+            standard_settings: self.standard.clone(),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Leg {
+    pub from: String,
+    pub to: String,
+    /// Minutes spent at `from` before this leg, 0 for the origin
+    pub dwell_minutes: u32,
+    /// Time this leg was queried for
+    pub time: DateTime<FixedOffset>,
+    pub routes: Routes,
+}
+
+/// Query a route from `stops[0]` to the last stop, passing all stops in between
+///
+/// With a single via stop and no dwell time the VVO API plans the whole route at once,
+/// the result then has exactly one leg.
+pub async fn plan(
+    backend: &dyn TransitBackend,
+    stops: &[Waypoint],
+    time: DateTime<FixedOffset>,
+    options: &RouteOptions,
+) -> anyhow::Result<Vec<Leg>> {
+    let [origin, vias @ .., destination] = stops else {
+        return Err(anyhow!("a route needs an origin and a destination"));
+    };
+
+    let single_query = match vias {
+        [] => true,
+        [via] => via.dwell_minutes == 0,
+        _ => false,
+    };
+    if single_query {
+        let via = vias.first().map(|via| via.stop_id.as_str());
+        let params = options.params(origin, destination, time.into(), via);
+        let routes = backend.route_details(&params).await?.into_inner();
+        return Ok(vec![Leg {
+            from: origin.name.clone(),
+            to: destination.name.clone(),
+            dwell_minutes: 0,
+            time,
+            routes,
+        }]);
+    }
+
+    let mut legs = Vec::with_capacity(stops.len() - 1);
+    let mut time = time;
+    if options.isarrivaltime {
+        for pair in stops.windows(2).rev() {
+            let (from, to) = (&pair[0], &pair[1]);
+            let leg = query_leg(backend, from, to, time, options).await?;
+            let departure = leg
+                .routes
+                .routes
+                .iter()
+                .filter_map(departure)
+                .max()
+                .ok_or_else(|| no_connection(from, to))?;
+            time = departure - Duration::minutes(from.dwell_minutes.into());
+            legs.push(leg);
+        }
+        legs.reverse();
+    } else {
+        for pair in stops.windows(2) {
+            let (from, to) = (&pair[0], &pair[1]);
+            let time_at_stop = time + Duration::minutes(from.dwell_minutes.into());
+            let leg = query_leg(backend, from, to, time_at_stop, options).await?;
+            time = leg
+                .routes
+                .routes
+                .iter()
+                .filter_map(arrival)
+                .min()
+                .ok_or_else(|| no_connection(from, to))?;
+            legs.push(leg);
+        }
+    }
+    Ok(legs)
+}
+
+async fn query_leg(
+    backend: &dyn TransitBackend,
+    from: &Waypoint,
+    to: &Waypoint,
+    time: DateTime<FixedOffset>,
+    options: &RouteOptions,
+) -> anyhow::Result<Leg> {
+    let params = options.params(from, to, time.into(), None);
+    let routes = backend
+        .route_details(&params)
+        .await
+        .map_err(|error| anyhow!("leg {} → {}: {error}", from.name, to.name))?
+        .into_inner();
+    Ok(Leg {
+        from: from.name.clone(),
+        to: to.name.clone(),
+        dwell_minutes: from.dwell_minutes,
+        time,
+        routes,
+    })
+}
+
+fn no_connection(from: &Waypoint, to: &Waypoint) -> anyhow::Error {
+    anyhow!("no connection from {} to {}", from.name, to.name)
+}
+
+/// Departure at the first stop of `route`, walks before it included
+pub fn departure(route: &Route) -> Option<DateTime<FixedOffset>> {
+    let partial_routes = route.partial_routes.as_deref()?;
+    let (index, time) = partial_routes
+        .iter()
+        .enumerate()
+        .find_map(|(index, part)| {
+            let stop = part.regular_stops.as_deref()?.first()?;
+            let time = stop
+                .departure_real_time
+                .as_ref()
+                .or(stop.departure_time.as_ref())?;
+            Some((index, time.to_datetime()))
+        })?;
+    let walk: u32 = partial_routes[..index]
+        .iter()
+        .filter_map(|part| part.duration)
+        .sum();
+    Some(time - Duration::minutes(walk.into()))
+}
+
+/// Arrival at the last stop of `route`, walks after it included
+pub fn arrival(route: &Route) -> Option<DateTime<FixedOffset>> {
+    let partial_routes = route.partial_routes.as_deref()?;
+    let (index, time) = partial_routes
+        .iter()
+        .enumerate()
+        .rev()
+        .find_map(|(index, part)| {
+            let stop = part.regular_stops.as_deref()?.last()?;
+            let time = stop
+                .arrival_real_time
+                .as_ref()
+                .or(stop.arrival_time.as_ref())?;
+            Some((index, time.to_datetime()))
+        })?;
+    let walk: u32 = partial_routes[index + 1..]
+        .iter()
+        .filter_map(|part| part.duration)
+        .sum();
+    Some(time + Duration::minutes(walk.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::backend::fixture::fixtures;
+
+    #[tokio::test]
+    async fn route_times_include_walks() {
+        let fixtures = fixtures();
+        let stop = |stop_id: &str| Waypoint {
+            name: stop_id.into(),
+            stop_id: stop_id.into(),
+            dwell_minutes: 0,
+        };
+        let options = RouteOptions {
+            isarrivaltime: false,
+            shorttermchanges: true,
+            mobility: None,
+            standard: None,
+        };
+        let (from, to) = (stop("33000028"), stop("33000013"));
+        let params = options.params(&from, &to, DvbTime::now(), None);
+        let routes = fixtures.route_details(&params).await.unwrap().into_inner();
+
+        // two minutes of walking to the tram leaving at 14:03
+        let route = &routes.routes[0];
+        assert_eq!(
+            departure(route).unwrap().to_rfc3339(),
+            "2026-10-16T14:01:00+02:00"
+        );
+        assert_eq!(
+            arrival(route).unwrap().to_rfc3339(),
+            "2026-10-16T14:15:00+02:00"
+        );
+    }
+}
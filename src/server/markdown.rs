@@ -0,0 +1,358 @@
+//! Markdown views of departure boards, routes and trips
+//!
+//! These are what the `departure-monitor` and `trip-tracker` prompts describe, rendered here
+//! so columns, delays and progress markers come out the same every time.
+
+use std::fmt::Write;
+
+use chrono::{DateTime, FixedOffset};
+use dvb::{
+    DvbTime,
+    monitor::{Departure, DepartureMonitor},
+    route::{PartialRoute, RegularStop, Route},
+};
+
+use crate::server::{
+    itinerary::{self, Leg},
+    progress::{StopStatus, TargetEta, TripProgress, TripStop, is_cancelled},
+};
+
+/// Departures within this many minutes also show how long is left
+const COUNTDOWN_MINUTES: i64 = 60;
+
+/// Table of `board`, with countdowns relative to `now`
+pub fn departures(board: &DepartureMonitor, now: DateTime<FixedOffset>) -> String {
+    let mut out = String::new();
+    let name = board.name.as_deref().unwrap_or("Departures");
+    match board.place.as_deref() {
+        Some(place) if !place.is_empty() => writeln!(out, "## {name} ({place})"),
+        _ => writeln!(out, "## {name}"),
+    }
+    .ok();
+    out.push('\n');
+
+    let departures = board.departures.as_deref().unwrap_or_default();
+    if departures.is_empty() {
+        out.push_str("No departures found.\n");
+        return out;
+    }
+
+    out.push_str("| Line | Destination | Departure | Platform | Status |\n");
+    out.push_str("|------|-------------|-----------|----------|--------|\n");
+    for departure in departures {
+        writeln!(
+            out,
+            "| {} | {} | {} | {} | {} |",
+            cell(&departure.line_name),
+            cell(&departure.direction),
+            departure_time(departure, now),
+            departure
+                .platform
+                .as_ref()
+                .map_or(String::new(), |platform| cell(&platform.name)),
+            departure_status(departure),
+        )
+        .ok();
+    }
+    out
+}
+
+/// The connections of every leg, one numbered list of rides and walks per connection
+pub fn routes(legs: &[Leg]) -> String {
+    let mut out = String::new();
+    for (number, leg) in legs.iter().enumerate() {
+        if legs.len() > 1 {
+            writeln!(out, "## Section {}: {} → {}", number + 1, leg.from, leg.to).ok();
+            if leg.dwell_minutes > 0 {
+                writeln!(out, "\nAfter {} min at {}.", leg.dwell_minutes, leg.from).ok();
+            }
+            out.push('\n');
+        }
+        if leg.routes.routes.is_empty() {
+            out.push_str("No connections found.\n\n");
+        }
+        for (index, route) in leg.routes.routes.iter().enumerate() {
+            route_summary(&mut out, index + 1, route);
+        }
+    }
+    out.truncate(out.trim_end().len());
+    out.push('\n');
+    out
+}
+
+/// Progress of a vehicle along its trip: passed stops ticked off, the next one marked
+pub fn trip(progress: &TripProgress) -> String {
+    let mut out = String::new();
+    let status = match &progress.next_stop {
+        Some(stop) if stop.cancelled => "Cancelled".into(),
+        Some(stop) => delay_text(stop.delay_minutes),
+        None => "Arrived".into(),
+    };
+    writeln!(out, "**Current Status:** {status}\n").ok();
+    if let Some(TargetEta { stop, minutes_away }) = &progress.target {
+        let time = stop.expected.format("%H:%M");
+        if *minutes_away >= 0 {
+            writeln!(
+                out,
+                "**Arriving at {}:** {time} (in {minutes_away} min)\n",
+                stop.name
+            )
+        } else {
+            writeln!(out, "**Passed {}** at {time}\n", stop.name)
+        }
+        .ok();
+    }
+    out.push_str("**Trip Progress:**\n");
+    let next = progress
+        .stops
+        .iter()
+        .position(|stop| stop.status == StopStatus::Upcoming);
+    for (index, stop) in progress.stops.iter().enumerate() {
+        trip_stop(&mut out, stop, next == Some(index));
+    }
+    out
+}
+
+fn route_summary(out: &mut String, number: usize, route: &Route) {
+    let parts = route.partial_routes.as_deref().unwrap_or_default();
+    write!(out, "**Connection {number}**").ok();
+    if let (Some(start), Some(end)) = (itinerary::departure(route), itinerary::arrival(route)) {
+        write!(out, ": {} → {}", start.format("%H:%M"), end.format("%H:%M")).ok();
+    }
+    let mut facts = Vec::new();
+    if let Some(duration) = route.duration {
+        facts.push(format!("{duration} min"));
+    }
+    if let Some(changes) = route.interchanges {
+        facts.push(match changes {
+            1 => "1 change".into(),
+            changes => format!("{changes} changes"),
+        });
+    }
+    if let Some(price) = &route.price {
+        facts.push(format!("{price} €"));
+    }
+    if !facts.is_empty() {
+        write!(out, " ({})", facts.join(", ")).ok();
+    }
+    if route.route_cancelled == Some(true) {
+        out.push_str(" **cancelled**");
+    }
+    out.push_str("\n\n");
+
+    for (index, part) in parts.iter().enumerate() {
+        write!(out, "{}. ", index + 1).ok();
+        partial_route(out, part);
+        out.push('\n');
+    }
+    out.push('\n');
+}
+
+fn partial_route(out: &mut String, part: &PartialRoute) {
+    let mot = part.mot.as_ref();
+    let kind = mot.and_then(|mot| mot.r#type.as_deref()).unwrap_or("");
+    let stops = part.regular_stops.as_deref().unwrap_or_default();
+    let (Some(first), Some(last)) = (stops.first(), stops.last()) else {
+        let minutes = part
+            .duration
+            .map(|duration| format!(" {duration} min"))
+            .unwrap_or_default();
+        match kind {
+            "Footpath" | "" => write!(out, "Walk{minutes}"),
+            "StayForConnection" => write!(out, "Wait{minutes}"),
+            other => write!(out, "{other}{minutes}"),
+        }
+        .ok();
+        return;
+    };
+
+    let name = mot.and_then(|mot| mot.name.as_deref()).unwrap_or("");
+    write!(out, "{kind} {name}").ok();
+    if let Some(direction) = mot.and_then(|mot| mot.direction.as_deref()) {
+        write!(out, " towards {direction}").ok();
+    }
+    write!(
+        out,
+        ": {} → {}",
+        stop_label(first, Side::Departure),
+        stop_label(last, Side::Arrival)
+    )
+    .ok();
+    if part.trip_cancelled == Some(true) {
+        out.push_str(" **cancelled**");
+    } else if part.changeover_endangered == Some(true) {
+        out.push_str(" (connection at risk)");
+    }
+}
+
+#[derive(Clone, Copy)]
+enum Side {
+    Arrival,
+    Departure,
+}
+
+fn stop_time(stop: &RegularStop, side: Side) -> Option<DvbTime> {
+    let (real, planned) = match side {
+        Side::Arrival => (&stop.arrival_real_time, &stop.arrival_time),
+        Side::Departure => (&stop.departure_real_time, &stop.departure_time),
+    };
+    real.as_ref().or(planned.as_ref()).cloned()
+}
+
+fn stop_label(stop: &RegularStop, side: Side) -> String {
+    let mut label = stop.name.clone().unwrap_or_default();
+    if let Some(time) = stop_time(stop, side) {
+        write!(label, " {}", clock(&time)).ok();
+    }
+    let planned = match side {
+        Side::Arrival => stop.arrival_time.as_ref(),
+        Side::Departure => stop.departure_time.as_ref(),
+    };
+    if let (Some(planned), Some(actual)) = (planned, stop_time(stop, side)) {
+        let delay = minutes_between(planned, &actual);
+        if delay != 0 {
+            write!(label, " ({delay:+} min)").ok();
+        }
+    }
+    if let Some(platform) = stop.platform.as_ref().and_then(|p| p.name.as_deref()) {
+        write!(label, ", platform {platform}").ok();
+    }
+    label
+}
+
+fn trip_stop(out: &mut String, stop: &TripStop, is_next: bool) {
+    let time = match stop.delay_minutes {
+        0 => stop.expected.format("%H:%M").to_string(),
+        delay => format!("{}, {delay:+} min", stop.expected.format("%H:%M")),
+    };
+    let line = match stop.status {
+        StopStatus::Passed => format!("✓ {} ({time}) - Departed", stop.name),
+        StopStatus::Current => format!("● {} ({time}) - At the stop now", stop.name),
+        StopStatus::Upcoming if is_next => format!("→ {} ({time}) - Next stop", stop.name),
+        StopStatus::Upcoming => format!("  {} ({time})", stop.name),
+    };
+    if stop.cancelled {
+        writeln!(out, "- {line} - **cancelled**").ok();
+    } else {
+        writeln!(out, "- {line}").ok();
+    }
+}
+
+fn departure_time(departure: &Departure, now: DateTime<FixedOffset>) -> String {
+    let Some(time) = departure
+        .real_time
+        .as_ref()
+        .or(departure.scheduled_time.as_ref())
+    else {
+        return String::new();
+    };
+    let minutes = (time.to_datetime() - now).num_minutes();
+    if (0..=COUNTDOWN_MINUTES).contains(&minutes) {
+        format!("{} (in {minutes} min)", clock(time))
+    } else {
+        clock(time)
+    }
+}
+
+fn departure_status(departure: &Departure) -> String {
+    if is_cancelled(departure.state.as_ref()) {
+        return match departure.cancel_reasons.as_slice() {
+            [] => "Cancelled".into(),
+            reasons => format!("Cancelled: {}", cell(&reasons.join("; "))),
+        };
+    }
+    match &departure.scheduled_time {
+        Some(scheduled) => delay_status(scheduled, departure.real_time.as_ref()),
+        None => String::new(),
+    }
+}
+
+fn delay_status(scheduled: &DvbTime, real: Option<&DvbTime>) -> String {
+    match real {
+        Some(real) => delay_text(minutes_between(scheduled, real)),
+        None => "Scheduled".into(),
+    }
+}
+
+fn delay_text(delay: i64) -> String {
+    match delay {
+        0 => "On time".into(),
+        delay if delay > 0 => format!("+{delay} min delay"),
+        early => format!("{} min early", -early),
+    }
+}
+
+fn minutes_between(from: &DvbTime, to: &DvbTime) -> i64 {
+    (to.to_datetime() - from.to_datetime()).num_minutes()
+}
+
+fn clock(time: &DvbTime) -> String {
+    time.to_datetime().format("%H:%M").to_string()
+}
+
+/// Keep user visible text from breaking the table
+fn cell(text: &str) -> String {
+    text.replace('|', "\\|").replace('\n', " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{TransitBackend, backend::fixture::fixtures};
+
+    fn at(time: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(time).unwrap()
+    }
+
+    #[tokio::test]
+    async fn departure_table() {
+        let board = fixtures()
+            .departure_monitor(dvb::monitor::Params {
+                stopid: "33000037",
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let table = departures(&board, at("2026-10-16T14:00:00+02:00"));
+        let lines = table.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "## Postplatz (Dresden)");
+        assert_eq!(
+            lines[2],
+            "| Line | Destination | Departure | Platform | Status |"
+        );
+        assert_eq!(lines[4], "| 1 | Prohlis | 14:02 (in 2 min) | 2 | On time |");
+        assert_eq!(
+            lines[5],
+            "| 2 | Gorbitz | 14:07 (in 7 min) | 1 | +3 min delay |"
+        );
+    }
+
+    #[tokio::test]
+    async fn trip_progress() {
+        let trip = fixtures()
+            .trip_details(&dvb::trip::Params {
+                tripid: "voe:11003: :H:j26",
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner();
+        let progress =
+            TripProgress::new(&trip, at("2026-10-16T14:16:30+02:00"), Some("Hauptbahnhof"));
+        let text = super::trip(&progress);
+        let lines = text.lines().collect::<Vec<_>>();
+        assert_eq!(lines[0], "**Current Status:** +1 min delay");
+        assert_eq!(lines[2], "**Arriving at Hauptbahnhof:** 14:21 (in 4 min)");
+        assert_eq!(lines[5], "- ✓ Albertplatz (14:12) - Departed");
+        assert_eq!(
+            lines[6],
+            "- ● Carolaplatz (14:16, +1 min) - At the stop now"
+        );
+        assert_eq!(
+            lines[7],
+            "- → Pirnaischer Platz (14:18, +1 min) - Next stop"
+        );
+        assert_eq!(lines[8], "-   Hauptbahnhof (14:21, +1 min)");
+    }
+}
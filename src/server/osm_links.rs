@@ -2,7 +2,7 @@ use anyhow::anyhow;
 use dvb::point::Point;
 use proj::Proj;
 
-use crate::server::args::DVBPointCoords;
+use crate::server::args::{DVBPointCoords, Wgs84Coords};
 
 /// Convert DVB projected coordinates to WGS84 latitude/longitude
 ///
@@ -38,6 +38,46 @@ fn dvb_coords_to_wgs84(coords: (i64, i64)) -> Result<(f64, f64), String> {
     Ok((latitude, longitude))
 }
 
+/// Convert WGS84 latitude/longitude to DVB projected coordinates
+///
+/// The inverse of [`dvb_coords_to_wgs84`], rounded to whole metres.
+///
+/// Returns: (northing, easting) in EPSG:31468, the order of DVB point coords
+fn wgs84_to_dvb_coords(latitude: f64, longitude: f64) -> Result<(i64, i64), String> {
+    if !((-90.0..=90.0).contains(&latitude) && (-180.0..=180.0).contains(&longitude)) {
+        return Err(format!(
+            "({}, {}) are no valid WGS84 coordinates",
+            latitude, longitude
+        ));
+    }
+
+    let proj = Proj::new_known_crs("EPSG:4326", "EPSG:31468", None)
+        .map_err(|e| format!("Failed to create projection: {}", e))?;
+
+    // proj takes (longitude, latitude) and returns (easting, northing)
+    let (easting, northing) = proj
+        .convert((longitude, latitude))
+        .map_err(|e| format!("Failed to transform coordinates: {}", e))?;
+    if !(easting.is_finite() && northing.is_finite()) {
+        return Err(format!(
+            "({}, {}) are outside the DVB projection",
+            latitude, longitude
+        ));
+    }
+
+    Ok((northing.round() as i64, easting.round() as i64))
+}
+
+/// The VVO point id of a GPS position, e.g. "coord:4621644:5657516"
+///
+/// Such ids are accepted wherever VVO takes a stop id or a PointFinder query,
+/// with easting before northing.
+pub fn dvb_point(coords: Wgs84Coords) -> anyhow::Result<String> {
+    let (northing, easting) = wgs84_to_dvb_coords(coords.latitude, coords.longitude)
+        .map_err(|error| anyhow!("Failed to convert coordinates: {error}"))?;
+    Ok(format!("coord:{easting}:{northing}"))
+}
+
 #[derive(Debug)]
 pub struct OsmCoords {
     pub latitude: f64,
@@ -83,6 +123,23 @@ impl TryFrom<Point> for OsmCoords {
     }
 }
 
+#[test]
+fn test_wgs84_coords_conversion() {
+    // Dresden Hauptbahnhof, see test_dvb_coords_conversion
+    let (lat, lon) = dvb_coords_to_wgs84((5657516, 4621644)).unwrap();
+    let (northing, easting) = wgs84_to_dvb_coords(lat, lon).unwrap();
+    assert_eq!((northing, easting), (5657516, 4621644));
+
+    let point = dvb_point(Wgs84Coords {
+        latitude: lat,
+        longitude: lon,
+    })
+    .unwrap();
+    assert_eq!(point, "coord:4621644:5657516");
+
+    assert!(wgs84_to_dvb_coords(91.0, 13.7).is_err());
+}
+
 #[test]
 fn test_dvb_coords_conversion() {
     // Dresden Hauptbahnhof coords from DVB: (5657516, 4621644)
@@ -0,0 +1,266 @@
+//! Where a vehicle is along its trip, worked out from the stop times of a VVO trip
+//!
+//! VVO marks stops as previous, current, next and onward as of the time the trip was
+//! queried. Here every stop is compared with "now" instead, using real-time data where
+//! there is some: a stop is current during the minute the vehicle is expected there.
+//!
+//! Trips are also published as `dvb://trips/{trip_id}` resources, see [`TripResource`].
+
+use chrono::{DateTime, Duration, FixedOffset};
+use dvb::{
+    DvbResponse, DvbTime,
+    trip::{self, Trip},
+};
+use rmcp::{schemars::JsonSchema, serde_json};
+use serde::Serialize;
+
+use crate::server::{TransitBackend, args::percent_decode};
+
+/// The trip behind a `dvb://trips/{trip_id}?stop_id=...&time=...` uri
+#[derive(Debug)]
+pub struct TripResource {
+    pub trip_id: String,
+    /// A stop of the trip, VVO needs one to find it
+    pub stop_id: String,
+    /// When the trip passes `stop_id`, telling trips on different days apart
+    pub time: DateTime<FixedOffset>,
+}
+
+impl TripResource {
+    /// `None` if `uri` is no trip, an error if its query is invalid
+    ///
+    /// Without a `time` in the query the trip is looked up for `now`.
+    pub fn parse(uri: &str, now: DateTime<FixedOffset>) -> Option<Result<Self, String>> {
+        let path = uri.strip_prefix("dvb://trips/")?;
+        let (trip_id, query) = path.split_once('?').unwrap_or((path, ""));
+        let mut stop_id = None;
+        let mut time = now;
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = percent_decode(value);
+            match key {
+                "stop_id" => stop_id = Some(value),
+                "time" => match DateTime::parse_from_rfc3339(&value) {
+                    Ok(parsed) => time = parsed,
+                    Err(error) => return Some(Err(format!("invalid time {value:?}: {error}"))),
+                },
+                _ => return Some(Err(format!("unknown parameter {key:?}"))),
+            }
+        }
+        let Some(stop_id) = stop_id else {
+            return Some(Err("stop_id is required to look up a trip".into()));
+        };
+        Some(Ok(Self {
+            trip_id: percent_decode(trip_id),
+            stop_id,
+            time,
+        }))
+    }
+
+    pub async fn fetch(&self, backend: &dyn TransitBackend) -> anyhow::Result<DvbResponse<Trip>> {
+        let params = trip::Params {
+            tripid: &self.trip_id,
+            time: self.time.into(),
+            stopid: &self.stop_id,
+            mapdata: None,
+        };
+        backend.trip_details(&params).await
+    }
+}
+
+/// Result of `get_trip_details`
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TripProgress {
+    /// The time progress was computed for
+    pub now: DateTime<FixedOffset>,
+    /// Minutes the vehicle is behind the timetable at its next stop, or at its last one
+    pub delay_minutes: i64,
+    /// The next stop the vehicle has not reached yet, none once the trip is over
+    pub next_stop: Option<TripStop>,
+    /// Arrival at the stop asked for in `target_stop`
+    pub target: Option<TargetEta>,
+    pub stops: Vec<TripStop>,
+}
+
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct TripStop {
+    pub stop_id: String,
+    pub name: String,
+    pub status: StopStatus,
+    /// Time according to the timetable
+    pub planned: DateTime<FixedOffset>,
+    /// Time according to real-time data, the planned time if there is none
+    pub expected: DateTime<FixedOffset>,
+    pub delay_minutes: i64,
+    pub cancelled: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum StopStatus {
+    /// The vehicle has left this stop
+    Passed,
+    /// The vehicle is at this stop right now
+    Current,
+    /// The vehicle has yet to reach this stop
+    Upcoming,
+}
+
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct TargetEta {
+    pub stop: TripStop,
+    /// Minutes from now until the vehicle gets there, negative if it already has
+    pub minutes_away: i64,
+}
+
+impl TripProgress {
+    /// Progress of `trip` at `now`, with the arrival at `target` (a stop id or name) if given
+    pub fn new(trip: &Trip, now: DateTime<FixedOffset>, target: Option<&str>) -> Self {
+        let stops = trip
+            .stops
+            .iter()
+            .map(|stop| {
+                let planned = stop.time.to_datetime();
+                let expected = stop
+                    .real_time
+                    .as_ref()
+                    .map_or(planned, DvbTime::to_datetime);
+                let status = if now < expected {
+                    StopStatus::Upcoming
+                } else if now < expected + Duration::minutes(1) {
+                    StopStatus::Current
+                } else {
+                    StopStatus::Passed
+                };
+                TripStop {
+                    stop_id: stop.id.clone(),
+                    name: stop.name.clone(),
+                    status,
+                    planned,
+                    expected,
+                    delay_minutes: (expected - planned).num_minutes(),
+                    cancelled: is_cancelled(stop.state.as_ref()),
+                }
+            })
+            .collect::<Vec<_>>();
+
+        let next_stop = stops
+            .iter()
+            .find(|stop| stop.status == StopStatus::Upcoming)
+            .cloned();
+        let delay_minutes = next_stop
+            .as_ref()
+            .or(stops.last())
+            .map_or(0, |stop| stop.delay_minutes);
+        let target = target.and_then(|target| {
+            let target = target.trim();
+            let stop = stops
+                .iter()
+                .find(|stop| stop.stop_id == target || stop.name.eq_ignore_ascii_case(target))?;
+            Some(TargetEta {
+                minutes_away: (stop.expected - now).num_minutes(),
+                stop: stop.clone(),
+            })
+        });
+
+        Self {
+            now,
+            delay_minutes,
+            next_stop,
+            target,
+            stops,
+        }
+    }
+}
+
+/// Whether a VVO arrival state marks a departure or stop as cancelled
+///
+/// dvb 0.8 keeps its `ArrivalState` in a private module, so the state is recognized by its
+/// serialized name. Match on the enum instead once the crate exports it.
+pub fn is_cancelled<S: Serialize>(state: Option<&S>) -> bool {
+    state
+        .and_then(|state| serde_json::to_value(state).ok())
+        .is_some_and(|state| state == "Cancelled")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{TransitBackend, backend::fixture::fixtures};
+
+    async fn trip() -> Trip {
+        fixtures()
+            .trip_details(&dvb::trip::Params {
+                tripid: "voe:11003: :H:j26",
+                ..Default::default()
+            })
+            .await
+            .unwrap()
+            .into_inner()
+    }
+
+    fn at(time: &str) -> DateTime<FixedOffset> {
+        DateTime::parse_from_rfc3339(time).unwrap()
+    }
+
+    fn statuses(progress: &TripProgress) -> Vec<StopStatus> {
+        progress.stops.iter().map(|stop| stop.status).collect()
+    }
+
+    #[tokio::test]
+    async fn stops_are_passed_by_real_time() {
+        use StopStatus::*;
+        let trip = trip().await;
+
+        // Carolaplatz is planned for 14:15 but the tram is a minute late
+        let progress = TripProgress::new(&trip, at("2026-10-16T14:15:30+02:00"), None);
+        assert_eq!(
+            statuses(&progress),
+            [Passed, Upcoming, Upcoming, Upcoming, Upcoming, Upcoming]
+        );
+        assert_eq!(progress.next_stop.unwrap().name, "Carolaplatz");
+        assert_eq!(progress.delay_minutes, 1);
+
+        let progress = TripProgress::new(&trip, at("2026-10-16T14:16:30+02:00"), None);
+        assert_eq!(
+            statuses(&progress),
+            [Passed, Current, Upcoming, Upcoming, Upcoming, Upcoming]
+        );
+        assert_eq!(progress.next_stop.unwrap().name, "Pirnaischer Platz");
+
+        let progress = TripProgress::new(&trip, at("2026-10-16T15:00:00+02:00"), None);
+        assert!(progress.stops.iter().all(|stop| stop.status == Passed));
+        assert!(progress.next_stop.is_none());
+        assert_eq!(progress.delay_minutes, 1);
+    }
+
+    #[tokio::test]
+    async fn eta_at_target_stop() {
+        let trip = trip().await;
+        let now = at("2026-10-16T14:16:30+02:00");
+
+        let progress = TripProgress::new(&trip, now, Some("hauptbahnhof"));
+        let target = progress.target.unwrap();
+        assert_eq!(target.stop.stop_id, "33000028");
+        assert_eq!(target.stop.expected, at("2026-10-16T14:21:00+02:00"));
+        assert_eq!(target.minutes_away, 4);
+
+        let progress = TripProgress::new(&trip, now, Some("33000013"));
+        assert_eq!(progress.target.unwrap().minutes_away, -4);
+
+        assert!(
+            TripProgress::new(&trip, now, Some("Postplatz"))
+                .target
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn cancelled_states_are_recognized() {
+        let mut stop = trip().await.stops.remove(0);
+        assert!(!is_cancelled(stop.state.as_ref()));
+
+        stop.state = serde_json::from_str(r#""Cancelled""#).unwrap();
+        assert!(is_cancelled(stop.state.as_ref()));
+    }
+}
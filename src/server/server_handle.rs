@@ -7,7 +7,27 @@ use rmcp::{
     tool_handler,
 };
 
-use crate::server::{DVBServer, usercontext::UserContext};
+use crate::server::{
+    DVBServer, TransitBackend,
+    backend::tracked::TrackedBackend,
+    board::BoardResource,
+    completion::{Completing, STOP_NAME_ARGUMENTS},
+    progress::{TripProgress, TripResource},
+    subscriptions::LiveResource,
+    usercontext::UserContext,
+};
+
+const DEPARTURES_TEMPLATE: &str = "dvb://departures/{stop_id}{?time,is_arrival,shorttermchanges}";
+const DEPARTURES_DESCRIPTION: &str = "Real-time departure information for a specific stop. \
+    Use the stop_id from find_stations or lookup_stop_id. Optionally pass an ISO8601 `time`, \
+    `is_arrival=true` for arrivals and `shorttermchanges`. \
+    Subscribe to be notified when the board changes.";
+
+const TRIPS_TEMPLATE: &str = "dvb://trips/{trip_id}{?stop_id,time}";
+const TRIPS_DESCRIPTION: &str = "Where a vehicle is along its trip and how late it is running. \
+    Use the trip_id and the stop_id of a stop of the trip from get_route_details; \
+    `time` (ISO8601) tells trips on different days apart and defaults to now. \
+    Subscribe to be notified when the trip changes, e.g. its delay.";
 
 #[tool_handler]
 #[prompt_handler]
@@ -18,6 +38,8 @@ impl ServerHandler for DVBServer {
                 .enable_tools()
                 .enable_prompts()
                 .enable_resources()
+                .enable_resources_subscribe()
+                .enable_completions()
                 .build(),
         )
         .with_server_info(Implementation::from_build_env())
@@ -30,7 +52,7 @@ impl ServerHandler for DVBServer {
              - Resources are automatically available - no tool call needed!\n\
              - For backward compatibility, get_user_context tool is also available\n\
              - Use elicit_origin/elicit_destination to save context for future use\n\
-             - Context persists for the session duration\n\n\
+             - Context persists for the session duration (across restarts if the server persists it)\n\n\
              **RECOMMENDED WORKFLOW**:\n\
              1. Read dvb://user/context resource to check existing context (automatic!)\n\
              2. If context exists, use it directly without asking redundant questions\n\
@@ -39,14 +61,18 @@ impl ServerHandler for DVBServer {
              **RESOURCES**:\n\
              - dvb://user/context: Complete user context (location + destination + status)\n\
              - dvb://user/location: Current user location (when set)\n\
-             - dvb://user/destination: Current user destination (when set)\n\n\
+             - dvb://user/destination: Current user destination (when set)\n\
+             - dvb://departures/{stop_id}: Departure board, subscribe to it to be notified of changes\n\
+             - dvb://trips/{trip_id}?stop_id=...: Progress of a trip, subscribe to it to follow delays\n\
+             - dvb://server/cache: Hit and miss counters of the response cache (when enabled)\n\n\
              **PROMPTS**:\n\
              - navigation-assistant: General transit navigation and route planning\n\
              - departure-monitor: Real-time departure boards for stations\n\
              - trip-tracker: Track specific trips in real-time (requires trip_id from route planning)\n\n\
              **TRIP TRACKING NOTE**:\n\
              Trip tracking requires a trip_id obtained from get_route_details. Store trip IDs \
-             in conversation context to provide updates when user asks about their journey.",
+             in conversation context to provide updates when user asks about their journey, \
+             or subscribe to dvb://trips/{trip_id} to be told when the trip changes.",
         )
     }
 
@@ -55,23 +81,21 @@ impl ServerHandler for DVBServer {
         _request: Option<PaginatedRequestParams>,
         _: RequestContext<RoleServer>,
     ) -> Result<ListResourcesResult, McpError> {
-        let origin = self.user_origin.lock().await.clone();
-        let location = self.user_location.lock().await.clone();
-        let destination = self.user_destination.lock().await.clone();
+        let saved = self.session.context();
 
         let mut resources = vec![
             RawResource::new("dvb://user/context", "User Context".to_string()).no_annotation(),
         ];
 
         // Add origin resource if set
-        if origin.is_some() {
+        if saved.origin.is_some() {
             resources.push(
                 RawResource::new("dvb://user/origin", "User Origin".to_string()).no_annotation(),
             );
         }
 
         // Add location resource if set
-        if location.is_some() {
+        if saved.location.is_some() {
             resources.push(
                 RawResource::new("dvb://user/location", "User Current Location".to_string())
                     .no_annotation(),
@@ -79,13 +103,20 @@ impl ServerHandler for DVBServer {
         }
 
         // Add destination resource if set
-        if destination.is_some() {
+        if saved.destination.is_some() {
             resources.push(
                 RawResource::new("dvb://user/destination", "User Destination".to_string())
                     .no_annotation(),
             );
         }
 
+        if self.backend.cache_stats().is_some() {
+            resources.push(
+                RawResource::new("dvb://server/cache", "Response Cache".to_string())
+                    .no_annotation(),
+            );
+        }
+
         Ok(ListResourcesResult {
             resources,
             next_cursor: None,
@@ -100,121 +131,77 @@ impl ServerHandler for DVBServer {
     ) -> Result<ReadResourceResult, McpError> {
         match uri.as_str() {
             "dvb://user/context" => {
-                let origin = self.user_origin.lock().await.clone();
-                let location = self.user_location.lock().await.clone();
-                let destination = self.user_destination.lock().await.clone();
-
-                let context = UserContext::new(origin, location, destination);
+                let context = UserContext::from(self.session.context());
 
                 Ok(ReadResourceResult::new(vec![ResourceContents::text(
                     serde_json::to_string_pretty(&context).unwrap(),
                     uri,
                 )]))
             }
-            "dvb://user/origin" => {
-                let origin = self.user_origin.lock().await.clone();
-
-                match origin {
-                    Some(org) => {
-                        let data = serde_json::json!({
-                            "origin": org,
-                            "last_updated": chrono::Local::now().to_rfc3339(),
-                        });
-
-                        Ok(ReadResourceResult::new(vec![ResourceContents::text(
-                            serde_json::to_string_pretty(&data).unwrap(),
-                            uri,
-                        )]))
-                    }
-                    None => Err(McpError::resource_not_found(
-                        "Origin not set",
-                        Some(serde_json::json!({ "uri": uri })),
-                    )),
-                }
-            }
-            "dvb://user/location" => {
-                let location = self.user_location.lock().await.clone();
-
-                match location {
-                    Some(loc) => {
-                        let data = serde_json::json!({
-                            "location": loc,
-                            "last_updated": chrono::Local::now().to_rfc3339(),
-                        });
-
-                        Ok(ReadResourceResult::new(vec![ResourceContents::text(
-                            serde_json::to_string_pretty(&data).unwrap(),
-                            uri,
-                        )]))
-                    }
-                    None => Err(McpError::resource_not_found(
-                        "Current location not set",
-                        Some(serde_json::json!({ "uri": uri })),
-                    )),
+            "dvb://user/origin" => match self.session.context().origin {
+                Some(org) => {
+                    let data = serde_json::json!({
+                        "origin": org,
+                        "last_updated": chrono::Local::now().to_rfc3339(),
+                    });
+
+                    Ok(ReadResourceResult::new(vec![ResourceContents::text(
+                        serde_json::to_string_pretty(&data).unwrap(),
+                        uri,
+                    )]))
                 }
-            }
-            "dvb://user/destination" => {
-                let destination = self.user_destination.lock().await.clone();
-
-                match destination {
-                    Some(dest) => {
-                        let data = serde_json::json!({
-                            "destination": dest,
-                            "last_updated": chrono::Local::now().to_rfc3339(),
-                        });
-
-                        Ok(ReadResourceResult::new(vec![ResourceContents::text(
-                            serde_json::to_string_pretty(&data).unwrap(),
-                            uri,
-                        )]))
-                    }
-                    None => Err(McpError::resource_not_found(
-                        "Destination not set",
-                        Some(serde_json::json!({ "uri": uri })),
-                    )),
+                None => Err(McpError::resource_not_found(
+                    "Origin not set",
+                    Some(serde_json::json!({ "uri": uri })),
+                )),
+            },
+            "dvb://user/location" => match self.session.context().location {
+                Some(loc) => {
+                    let data = serde_json::json!({
+                        "location": loc,
+                        "last_updated": chrono::Local::now().to_rfc3339(),
+                    });
+
+                    Ok(ReadResourceResult::new(vec![ResourceContents::text(
+                        serde_json::to_string_pretty(&data).unwrap(),
+                        uri,
+                    )]))
                 }
-            }
-            _ => {
-                // Check if it's a departures resource with pattern dvb://departures/{stop_id}
-                if uri.starts_with("dvb://departures/") {
-                    let stop_id = uri.strip_prefix("dvb://departures/").unwrap();
-
-                    // Fetch departures using dvb crate
-                    let monitor_params = dvb::monitor::Params {
-                        stopid: stop_id,
-                        mot: None,
-                        limit: Some(10),
-                        ..Default::default()
-                    };
-
-                    match dvb::monitor::departure_monitor(monitor_params).await {
-                        Ok(departures) => {
-                            let data = serde_json::json!({
-                                "stop_id": stop_id,
-                                "departures": departures,
-                                "last_updated": chrono::Local::now().to_rfc3339(),
-                            });
-
-                            Ok(ReadResourceResult::new(vec![ResourceContents::text(
-                                serde_json::to_string_pretty(&data).unwrap(),
-                                uri,
-                            )]))
-                        }
-                        Err(error) => Err(McpError::resource_not_found(
-                            format!(
-                                "Failed to fetch departures for stop_id {}: {}",
-                                stop_id, error
-                            ),
-                            Some(serde_json::json!({ "uri": uri, "stop_id": stop_id })),
-                        )),
-                    }
-                } else {
-                    Err(McpError::resource_not_found(
-                        "Resource not found",
-                        Some(serde_json::json!({ "uri": uri })),
-                    ))
+                None => Err(McpError::resource_not_found(
+                    "Current location not set",
+                    Some(serde_json::json!({ "uri": uri })),
+                )),
+            },
+            "dvb://user/destination" => match self.session.context().destination {
+                Some(dest) => {
+                    let data = serde_json::json!({
+                        "destination": dest,
+                        "last_updated": chrono::Local::now().to_rfc3339(),
+                    });
+
+                    Ok(ReadResourceResult::new(vec![ResourceContents::text(
+                        serde_json::to_string_pretty(&data).unwrap(),
+                        uri,
+                    )]))
                 }
-            }
+                None => Err(McpError::resource_not_found(
+                    "Destination not set",
+                    Some(serde_json::json!({ "uri": uri })),
+                )),
+            },
+            "dvb://server/cache" => match self.backend.cache_stats() {
+                Some(stats) => Ok(ReadResourceResult::new(vec![ResourceContents::text(
+                    serde_json::to_string_pretty(&stats).unwrap(),
+                    uri,
+                )])),
+                None => Err(McpError::resource_not_found(
+                    "Responses are not cached",
+                    Some(serde_json::json!({ "uri": uri })),
+                )),
+            },
+            _ => Ok(ReadResourceResult::new(vec![
+                self.live_contents(&*self.backend, uri).await?,
+            ])),
         }
     }
 
@@ -224,12 +211,17 @@ impl ServerHandler for DVBServer {
         _: RequestContext<RoleServer>,
     ) -> Result<ListResourceTemplatesResult, McpError> {
         let templates = vec![
-            RawResourceTemplate::new("dvb://departures/{stop_id}", "Station Departures")
+            RawResourceTemplate::new(DEPARTURES_TEMPLATE, "Station Departures")
                 .with_title("Real-time Departures")
-                .with_description("Real-time departure information for a specific stop. Use the stop_id from find_stations or lookup_stop_id.")
+                .with_description(DEPARTURES_DESCRIPTION)
                 .with_mime_type("application/json")
                 .no_annotation(),
-    ];
+            RawResourceTemplate::new(TRIPS_TEMPLATE, "Trip Progress")
+                .with_title("Real-time Trip Progress")
+                .with_description(TRIPS_DESCRIPTION)
+                .with_mime_type("application/json")
+                .no_annotation(),
+        ];
 
         Ok(ListResourceTemplatesResult {
             next_cursor: None,
@@ -237,4 +229,134 @@ impl ServerHandler for DVBServer {
             meta: None,
         })
     }
+
+    async fn subscribe(
+        &self,
+        SubscribeRequestParams { uri, .. }: SubscribeRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        let Some(resource) = LiveResource::parse(&uri, self.clock.now()) else {
+            return Err(McpError::invalid_params(
+                "Only departure boards (dvb://departures/{stop_id}) and trips (dvb://trips/{trip_id}) can be subscribed to",
+                Some(serde_json::json!({ "uri": uri })),
+            ));
+        };
+        let resource = resource.map_err(|error| {
+            McpError::invalid_params(error, Some(serde_json::json!({ "uri": uri })))
+        })?;
+        self.subscriptions.subscribe(uri, resource, context.peer);
+        Ok(())
+    }
+
+    async fn complete(
+        &self,
+        CompleteRequestParams {
+            r#ref, argument, ..
+        }: CompleteRequestParams,
+        context: RequestContext<RoleServer>,
+    ) -> Result<CompleteResult, McpError> {
+        let completing = match &r#ref {
+            Reference::Resource(ResourceReference { uri })
+                if argument.name == "stop_id"
+                    && (uri.starts_with("dvb://departures/")
+                        || uri.starts_with("dvb://trips/")) =>
+            {
+                Completing::StopId
+            }
+            Reference::Prompt(_) if argument.name == "stop_id" => Completing::StopId,
+            Reference::Prompt(_) if STOP_NAME_ARGUMENTS.contains(&argument.name.as_str()) => {
+                Completing::StopName
+            }
+            _ => return Ok(CompleteResult::default()),
+        };
+
+        let backend = TrackedBackend::new(&*self.backend, &context);
+        let completion = self
+            .completions
+            .complete(
+                &backend,
+                &self.session.context(),
+                completing,
+                &argument.value,
+            )
+            .await;
+        Ok(CompleteResult::new(completion))
+    }
+
+    async fn unsubscribe(
+        &self,
+        UnsubscribeRequestParams { uri, .. }: UnsubscribeRequestParams,
+        _: RequestContext<RoleServer>,
+    ) -> Result<(), McpError> {
+        self.subscriptions.unsubscribe(&uri);
+        Ok(())
+    }
+}
+
+impl DVBServer {
+    /// Current contents of a departure board or trip resource, fetched from `backend`
+    pub async fn live_contents(
+        &self,
+        backend: &dyn TransitBackend,
+        uri: String,
+    ) -> Result<ResourceContents, McpError> {
+        // Check if it's a departures resource with pattern dvb://departures/{stop_id}
+        if let Some(board) = BoardResource::parse(&uri) {
+            let board = board.map_err(|error| {
+                McpError::invalid_params(error, Some(serde_json::json!({ "uri": uri })))
+            })?;
+            let stop_id = &board.stop_id;
+
+            match board.fetch(backend).await {
+                Ok(departures) => {
+                    let data = serde_json::json!({
+                        "stop_id": stop_id,
+                        "departures": departures,
+                        "last_updated": chrono::Local::now().to_rfc3339(),
+                    });
+
+                    Ok(ResourceContents::text(
+                        serde_json::to_string_pretty(&data).unwrap(),
+                        uri,
+                    ))
+                }
+                Err(error) => Err(McpError::resource_not_found(
+                    format!(
+                        "Failed to fetch departures for stop_id {}: {}",
+                        stop_id, error
+                    ),
+                    Some(serde_json::json!({ "uri": uri, "stop_id": stop_id })),
+                )),
+            }
+        } else if let Some(trip) = TripResource::parse(&uri, self.clock.now()) {
+            let trip = trip.map_err(|error| {
+                McpError::invalid_params(error, Some(serde_json::json!({ "uri": uri })))
+            })?;
+
+            match trip.fetch(backend).await {
+                Ok(response) => {
+                    let data = serde_json::json!({
+                        "trip_id": trip.trip_id,
+                        "stop_id": trip.stop_id,
+                        "trip": TripProgress::new(&response, self.clock.now(), None),
+                        "last_updated": chrono::Local::now().to_rfc3339(),
+                    });
+
+                    Ok(ResourceContents::text(
+                        serde_json::to_string_pretty(&data).unwrap(),
+                        uri,
+                    ))
+                }
+                Err(error) => Err(McpError::resource_not_found(
+                    format!("Failed to fetch trip {}: {}", trip.trip_id, error),
+                    Some(serde_json::json!({ "uri": uri, "trip_id": trip.trip_id })),
+                )),
+            }
+        } else {
+            Err(McpError::resource_not_found(
+                "Resource not found",
+                Some(serde_json::json!({ "uri": uri })),
+            ))
+        }
+    }
 }
@@ -0,0 +1,227 @@
+//! Live departure boards and trips for clients subscribed to their resources
+//!
+//! Each subscribed resource is polled by one background task, shared by every session
+//! subscribed to the same uri. Subscribers get `notifications/resources/updated` only when
+//! the board or trip differs from the previous poll. The task stops once the last subscriber
+//! unsubscribes, its session ends or it can no longer be notified.
+
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex, MutexGuard, PoisonError,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, FixedOffset};
+use rmcp::{
+    model::ResourceUpdatedNotificationParam,
+    serde_json::{self, Value},
+    service::{Peer, RoleServer},
+};
+use tokio::task::JoinHandle;
+
+use crate::server::{TransitBackend, board::BoardResource, progress::TripResource};
+
+/// Time between two polls of a subscribed resource
+pub const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+/// A resource that can be subscribed to
+#[derive(Debug)]
+pub enum LiveResource {
+    Departures(BoardResource),
+    Trip(TripResource),
+}
+
+impl LiveResource {
+    /// `None` if `uri` cannot be subscribed to, an error if its query is invalid
+    pub fn parse(uri: &str, now: DateTime<FixedOffset>) -> Option<Result<Self, String>> {
+        if let Some(board) = BoardResource::parse(uri) {
+            return Some(board.map(Self::Departures));
+        }
+        TripResource::parse(uri, now).map(|trip| trip.map(Self::Trip))
+    }
+
+    /// What is compared between two polls
+    ///
+    /// The expiration time changes with every response, only the board or trip itself counts.
+    async fn content(&self, backend: &dyn TransitBackend) -> anyhow::Result<Value> {
+        Ok(match self {
+            Self::Departures(board) => {
+                serde_json::to_value(board.fetch(backend).await?.into_inner())?
+            }
+            Self::Trip(trip) => serde_json::to_value(trip.fetch(backend).await?.into_inner())?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+struct SubscriberKey(u64);
+
+/// Subscribed resources of all sessions served by one server
+#[derive(Clone)]
+pub struct Subscriptions {
+    backend: Arc<dyn TransitBackend>,
+    interval: Duration,
+    next_key: Arc<AtomicU64>,
+    pollers: Arc<Mutex<HashMap<String, Poller>>>,
+}
+
+/// The task polling one resource and the sessions waiting for it to change
+struct Poller {
+    subscribers: HashMap<SubscriberKey, Peer<RoleServer>>,
+    task: JoinHandle<()>,
+}
+
+impl Drop for Poller {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+impl Subscriptions {
+    pub fn new(backend: Arc<dyn TransitBackend>) -> Self {
+        Self {
+            backend,
+            interval: POLL_INTERVAL,
+            next_key: Default::default(),
+            pollers: Default::default(),
+        }
+    }
+
+    /// Poll every `interval` instead of [`POLL_INTERVAL`]
+    #[cfg(test)]
+    pub fn with_poll_interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Subscriptions of one session, all of them end when it is dropped
+    pub fn open_session(&self) -> Subscriber {
+        Subscriber {
+            key: SubscriberKey(self.next_key.fetch_add(1, Ordering::Relaxed)),
+            subscriptions: self.clone(),
+        }
+    }
+
+    /// Uris currently being polled
+    #[cfg(test)]
+    pub fn polled(&self) -> Vec<String> {
+        self.pollers().keys().cloned().collect()
+    }
+
+    fn pollers(&self) -> MutexGuard<'_, HashMap<String, Poller>> {
+        self.pollers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn subscribe(
+        &self,
+        key: SubscriberKey,
+        uri: String,
+        resource: LiveResource,
+        peer: Peer<RoleServer>,
+    ) {
+        let mut pollers = self.pollers();
+        let poller = pollers.entry(uri.clone()).or_insert_with(|| {
+            tracing::debug!("polling {uri} every {:?}", self.interval);
+            Poller {
+                subscribers: HashMap::new(),
+                task: tokio::spawn(poll(self.clone(), uri, resource)),
+            }
+        });
+        poller.subscribers.insert(key, peer);
+    }
+
+    fn unsubscribe(&self, key: SubscriberKey, uri: &str) {
+        let mut pollers = self.pollers();
+        if let Some(poller) = pollers.get_mut(uri) {
+            poller.subscribers.remove(&key);
+            if poller.subscribers.is_empty() {
+                tracing::debug!("no subscribers left, no longer polling {uri}");
+                pollers.remove(uri);
+            }
+        }
+    }
+
+    fn unsubscribe_all(&self, key: SubscriberKey) {
+        self.pollers().retain(|uri, poller| {
+            poller.subscribers.remove(&key);
+            if poller.subscribers.is_empty() {
+                tracing::debug!("session ended, no longer polling {uri}");
+            }
+            !poller.subscribers.is_empty()
+        });
+    }
+
+    fn subscribers(&self, uri: &str) -> Vec<(SubscriberKey, Peer<RoleServer>)> {
+        self.pollers()
+            .get(uri)
+            .map(|poller| {
+                poller
+                    .subscribers
+                    .iter()
+                    .map(|(key, peer)| (*key, peer.clone()))
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+}
+
+/// Subscriptions of one session
+pub struct Subscriber {
+    key: SubscriberKey,
+    subscriptions: Subscriptions,
+}
+
+impl Subscriber {
+    /// Notify `peer` whenever the resource at `uri` changes
+    pub fn subscribe(&self, uri: String, resource: LiveResource, peer: Peer<RoleServer>) {
+        self.subscriptions.subscribe(self.key, uri, resource, peer);
+    }
+
+    pub fn unsubscribe(&self, uri: &str) {
+        self.subscriptions.unsubscribe(self.key, uri);
+    }
+
+    pub fn subscriptions(&self) -> &Subscriptions {
+        &self.subscriptions
+    }
+}
+
+impl Drop for Subscriber {
+    fn drop(&mut self) {
+        self.subscriptions.unsubscribe_all(self.key);
+    }
+}
+
+/// Fetch the resource at `uri` over and over, notifying its subscribers of every change
+///
+/// Runs until its [`Poller`] is dropped.
+async fn poll(subscriptions: Subscriptions, uri: String, resource: LiveResource) {
+    let mut ticks = tokio::time::interval(subscriptions.interval);
+    let mut last: Option<Value> = None;
+    loop {
+        ticks.tick().await;
+        let current = match resource.content(&*subscriptions.backend).await {
+            Ok(content) => Some(content),
+            Err(error) => {
+                tracing::warn!("polling {uri} failed: {error}");
+                continue;
+            }
+        };
+        if last.is_none() || current == last {
+            last = current;
+            continue;
+        }
+        last = current;
+
+        for (key, peer) in subscriptions.subscribers(&uri) {
+            let notification = ResourceUpdatedNotificationParam::new(uri.clone());
+            if let Err(error) = peer.notify_resource_updated(notification).await {
+                tracing::debug!("dropping subscriber of {uri}: {error}");
+                subscriptions.unsubscribe(key, &uri);
+            }
+        }
+    }
+}
@@ -0,0 +1,280 @@
+//! Compact view of VVO routes, published as the output schema of `get_route_details`
+//!
+//! The raw route payload repeats every intermediate stop, map data and ticket table.
+//! A [`RouteSummary`] keeps what a traveller asks about: when and where to get on and off,
+//! which line to take, how long it takes and how late it is running.
+
+use chrono::{DateTime, FixedOffset};
+use dvb::{
+    DvbTime,
+    route::{PartialRoute, RegularStop, Route},
+};
+use rmcp::schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::server::{args::DetailLevel, itinerary::Leg, usercontext::ContextValue};
+
+/// Result of `get_route_details`
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RouteDetails {
+    /// Connections from origin to destination, empty if the journey was planned in `sections`
+    pub routes: Vec<RouteSummary>,
+    /// Parts of a journey through via stops with dwell times, each planned on its own
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub sections: Vec<RouteSection>,
+    /// Request arguments that were taken from the saved user context
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub from_context: Vec<ContextValue>,
+}
+
+impl RouteDetails {
+    pub fn new(legs: &[Leg], detail: DetailLevel, from_context: Vec<ContextValue>) -> Self {
+        let (routes, sections) = match legs {
+            [leg] => (summarize(leg, detail), Vec::new()),
+            legs => (
+                Vec::new(),
+                legs.iter()
+                    .map(|leg| RouteSection::new(leg, detail))
+                    .collect(),
+            ),
+        };
+        Self {
+            routes,
+            sections,
+            from_context,
+        }
+    }
+}
+
+/// Connections between two stops of a journey with via stops
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RouteSection {
+    pub from: String,
+    pub to: String,
+    /// Minutes spent at `from` before this section, 0 for the origin
+    pub dwell_minutes: u32,
+    /// Time this section was queried for
+    pub time: DateTime<FixedOffset>,
+    pub routes: Vec<RouteSummary>,
+}
+
+impl RouteSection {
+    fn new(leg: &Leg, detail: DetailLevel) -> Self {
+        Self {
+            from: leg.from.clone(),
+            to: leg.to.clone(),
+            dwell_minutes: leg.dwell_minutes,
+            time: leg.time,
+            routes: summarize(leg, detail),
+        }
+    }
+}
+
+/// One connection from start to end
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RouteSummary {
+    /// Door to door travel time
+    pub duration_minutes: Option<u32>,
+    /// Number of times to change vehicles
+    pub changes: Option<u32>,
+    /// Time spent walking, to, between and from stops
+    pub walking_minutes: u32,
+    /// Where and when the first vehicle is boarded
+    pub departure: Option<StopTime>,
+    /// Where and when the last vehicle is left
+    pub arrival: Option<StopTime>,
+    /// Single ticket price in euros, e.g. "3,00"
+    pub price: Option<String>,
+    pub cancelled: bool,
+    /// Rides and walks in order, left out at detail level "summary"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub legs: Vec<RouteLeg>,
+}
+
+impl RouteSummary {
+    pub fn new(route: &Route, detail: DetailLevel) -> Self {
+        let mut legs = route
+            .partial_routes
+            .iter()
+            .flatten()
+            .map(|part| RouteLeg::new(part, detail))
+            .collect::<Vec<_>>();
+        let walking_minutes = legs
+            .iter()
+            .filter(|leg| leg.mode == "Footpath")
+            .filter_map(|leg| leg.duration_minutes)
+            .sum();
+        let departure = legs.iter().find_map(|leg| leg.from.clone());
+        let arrival = legs.iter().rev().find_map(|leg| leg.to.clone());
+        if detail == DetailLevel::Summary {
+            legs.clear();
+        }
+        Self {
+            duration_minutes: route.duration,
+            changes: route.interchanges,
+            walking_minutes,
+            departure,
+            arrival,
+            price: route.price.clone(),
+            cancelled: route.route_cancelled.unwrap_or(false),
+            legs,
+        }
+    }
+}
+
+/// A ride on one vehicle, or a walk
+#[derive(Debug, Serialize, JsonSchema)]
+pub struct RouteLeg {
+    /// Mode of transport as named by VVO, e.g. "Tram", "CityBus" or "Footpath"
+    pub mode: String,
+    /// Line name, e.g. "11"
+    pub line: Option<String>,
+    /// Final destination shown on the vehicle
+    pub direction: Option<String>,
+    pub from: Option<StopTime>,
+    pub to: Option<StopTime>,
+    pub duration_minutes: Option<u32>,
+    /// Pass to `get_trip_details` together with the `stop_id` of `from` to follow the vehicle
+    pub trip_id: Option<String>,
+    pub cancelled: bool,
+    /// Every stop of the ride from boarding to leaving
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub stops: Vec<StopTime>,
+    /// Notices about this ride, e.g. diversions, only at detail level "full"
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub notes: Vec<String>,
+}
+
+impl RouteLeg {
+    fn new(part: &PartialRoute, detail: DetailLevel) -> Self {
+        let mot = part.mot.as_ref();
+        let stops = part.regular_stops.as_deref().unwrap_or_default();
+        let full = detail == DetailLevel::Full;
+        Self {
+            mode: mot
+                .and_then(|mot| mot.r#type.clone())
+                .unwrap_or_else(|| "Footpath".into()),
+            line: mot.and_then(|mot| mot.name.clone()),
+            direction: mot.and_then(|mot| mot.direction.clone()),
+            from: stops
+                .first()
+                .map(|stop| StopTime::new(stop, &stop.departure_time, &stop.departure_real_time)),
+            to: stops
+                .last()
+                .map(|stop| StopTime::new(stop, &stop.arrival_time, &stop.arrival_real_time)),
+            duration_minutes: part.duration,
+            trip_id: mot.and_then(|mot| mot.stateless_id.clone()),
+            cancelled: part.trip_cancelled.unwrap_or(false),
+            stops: if detail != DetailLevel::Summary {
+                stops
+                    .iter()
+                    .enumerate()
+                    .map(|(index, stop)| {
+                        if index + 1 == stops.len() {
+                            StopTime::new(stop, &stop.arrival_time, &stop.arrival_real_time)
+                        } else {
+                            StopTime::new(stop, &stop.departure_time, &stop.departure_real_time)
+                        }
+                    })
+                    .collect()
+            } else {
+                Vec::new()
+            },
+            notes: part.infos.clone().filter(|_| full).unwrap_or_default(),
+        }
+    }
+}
+
+/// A stop with the time a vehicle leaves or reaches it
+#[derive(Debug, Clone, Serialize, JsonSchema)]
+pub struct StopTime {
+    pub stop_id: Option<String>,
+    pub name: String,
+    pub platform: Option<String>,
+    /// Time according to the timetable
+    pub planned: Option<DateTime<FixedOffset>>,
+    /// Time according to real-time data, if there is any
+    pub expected: Option<DateTime<FixedOffset>>,
+    /// How much later than planned, negative if early
+    pub delay_minutes: i64,
+}
+
+impl StopTime {
+    fn new(stop: &RegularStop, planned: &Option<DvbTime>, expected: &Option<DvbTime>) -> Self {
+        let planned = planned.as_ref().map(DvbTime::to_datetime);
+        let expected = expected.as_ref().map(DvbTime::to_datetime);
+        let delay_minutes = match (planned, expected) {
+            (Some(planned), Some(expected)) => (expected - planned).num_minutes(),
+            _ => 0,
+        };
+        Self {
+            stop_id: stop.data_id.clone(),
+            name: stop.name.clone().unwrap_or_default(),
+            platform: stop.platform.as_ref().and_then(|p| p.name.clone()),
+            planned,
+            expected,
+            delay_minutes,
+        }
+    }
+}
+
+fn summarize(leg: &Leg, detail: DetailLevel) -> Vec<RouteSummary> {
+    leg.routes
+        .routes
+        .iter()
+        .map(|route| RouteSummary::new(route, detail))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::server::{TransitBackend, backend::fixture::fixtures};
+
+    async fn route() -> Route {
+        let params = dvb::route::Params {
+            origin: "33000028",
+            destination: "33000013",
+            time: DvbTime::now(),
+            isarrivaltime: false,
+            shorttermchanges: true,
+            format: "json",
+            via: None,
+            mobility_settings: None,
+            standard_settings: None,
+        };
+        let mut routes = fixtures()
+            .route_details(&params)
+            .await
+            .unwrap()
+            .into_inner();
+        routes.routes.remove(0)
+    }
+
+    #[tokio::test]
+    async fn detail_levels() {
+        let route = route().await;
+
+        let summary = RouteSummary::new(&route, DetailLevel::Summary);
+        assert!(summary.legs.is_empty());
+        assert_eq!(summary.departure.unwrap().name, "Hauptbahnhof");
+        assert_eq!(summary.walking_minutes, 2);
+
+        let legs = RouteSummary::new(&route, DetailLevel::Legs).legs;
+        assert_eq!(legs.len(), 2);
+        assert_eq!(legs[0].mode, "Footpath");
+        assert_eq!(legs[0].duration_minutes, Some(2));
+        assert_eq!(legs[1].trip_id.as_deref(), Some("voe:11003: :H:j26"));
+        let stops = &legs[1].stops;
+        let names = stops
+            .iter()
+            .map(|stop| stop.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["Hauptbahnhof", "Pirnaischer Platz", "Albertplatz"]);
+        assert_eq!(stops[1].platform.as_deref(), Some("2"));
+        assert!(legs[1].notes.is_empty());
+
+        let full = RouteSummary::new(&route, DetailLevel::Full).legs;
+        assert_eq!(full[1].stops.len(), 3);
+    }
+}
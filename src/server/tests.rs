@@ -0,0 +1,1481 @@
+//! Protocol level tests, talking to the server through an in-memory MCP client
+
+use std::{sync::Arc, time::Duration};
+
+use chrono::DateTime;
+use dvb::{
+    DvbResponse,
+    lines::Lines,
+    monitor::{self, DepartureMonitor},
+    point::Found,
+    route::{self, Routes},
+    trip::{self, Trip},
+};
+use tokio::{sync::mpsc, time::timeout};
+use tokio_util::sync::CancellationToken;
+
+use rmcp::{
+    ClientHandler, ErrorData as McpError, RoleClient, ServiceExt,
+    model::{
+        CallToolRequest, CallToolRequestParams, CallToolResult, ClientCapabilities, ClientInfo,
+        ClientRequest, CreateElicitationRequestParams, CreateElicitationResult, ElicitationAction,
+        GetPromptRequestParams, Implementation, ProgressNotificationParam, PromptMessage,
+        PromptMessageContent, ReadResourceRequestParams, ResourceContents,
+        ResourceUpdatedNotificationParam, SubscribeRequestParams, UnsubscribeRequestParams,
+    },
+    serde_json::{self, Value, json},
+    service::{NotificationContext, PeerRequestOptions, RequestContext, RunningService},
+};
+
+use crate::server::{
+    DVBServer, TransitBackend, UserContextStore,
+    backend::{
+        BackendFuture,
+        fixture::{cached_fixtures, fixtures},
+    },
+    clock::Clock,
+};
+
+type Client<C = ()> = RunningService<RoleClient, C>;
+
+/// Server answering from the canned responses in `fixtures/`, at the time they were taken
+fn fixture_server() -> DVBServer {
+    let now = DateTime::parse_from_rfc3339("2026-10-16T14:16:30+02:00").unwrap();
+    DVBServer::new(UserContextStore::default(), Arc::new(fixtures())).with_clock(Clock::fixed(now))
+}
+
+/// Serve `server` over an in-memory transport and connect a client to it
+async fn connect(server: DVBServer) -> Client {
+    connect_with(server, ()).await
+}
+
+async fn connect_with<C: ClientHandler>(server: DVBServer, client: C) -> Client<C> {
+    let (server_io, client_io) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move {
+        let running = server.serve(server_io).await.expect("server starts");
+        let _ = running.waiting().await;
+    });
+    client.serve(client_io).await.expect("client connects")
+}
+
+/// Client supporting elicitation, answering every form with `answer`
+struct Answering {
+    answer: Option<Value>,
+    asked: std::sync::Mutex<Vec<Value>>,
+}
+
+impl Answering {
+    fn new(answer: Option<Value>) -> Self {
+        Self {
+            answer,
+            asked: Default::default(),
+        }
+    }
+}
+
+impl ClientHandler for Answering {
+    async fn create_elicitation(
+        &self,
+        request: CreateElicitationRequestParams,
+        _context: RequestContext<RoleClient>,
+    ) -> Result<CreateElicitationResult, McpError> {
+        if let CreateElicitationRequestParams::FormElicitationParams {
+            requested_schema, ..
+        } = request
+        {
+            self.asked
+                .lock()
+                .unwrap()
+                .push(serde_json::to_value(requested_schema).unwrap());
+        }
+        Ok(match &self.answer {
+            Some(answer) => {
+                CreateElicitationResult::new(ElicitationAction::Accept).with_content(answer.clone())
+            }
+            None => CreateElicitationResult::new(ElicitationAction::Decline),
+        })
+    }
+
+    fn get_info(&self) -> ClientInfo {
+        ClientInfo::new(
+            ClientCapabilities::builder().enable_elicitation().build(),
+            Implementation::new("answering-client", "0.1.0"),
+        )
+    }
+}
+
+/// Client passing on the uris of resources it is told were updated
+struct Watching(mpsc::UnboundedSender<String>);
+
+impl ClientHandler for Watching {
+    async fn on_resource_updated(
+        &self,
+        params: ResourceUpdatedNotificationParam,
+        _context: NotificationContext<RoleClient>,
+    ) {
+        let _ = self.0.send(params.uri);
+    }
+}
+
+/// Client passing on the messages of progress notifications
+struct Reporting(mpsc::UnboundedSender<String>);
+
+impl ClientHandler for Reporting {
+    async fn on_progress(
+        &self,
+        params: ProgressNotificationParam,
+        _context: NotificationContext<RoleClient>,
+    ) {
+        let _ = self.0.send(params.message.unwrap_or_default());
+    }
+}
+
+/// Backend whose calls never finish, cancelling `dropped` once one of them is given up
+struct Stalled {
+    dropped: CancellationToken,
+}
+
+impl Stalled {
+    fn stall<'a, T: 'a>(&self) -> BackendFuture<'a, T> {
+        let guard = self.dropped.clone().drop_guard();
+        Box::pin(async move {
+            let _guard = guard;
+            std::future::pending().await
+        })
+    }
+}
+
+impl TransitBackend for Stalled {
+    fn find_stops<'a>(&'a self, _: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        self.stall()
+    }
+
+    fn find_nearby_stops<'a>(&'a self, _: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        self.stall()
+    }
+
+    fn find_pois<'a>(&'a self, _: &'a str) -> BackendFuture<'a, DvbResponse<Found>> {
+        self.stall()
+    }
+
+    fn departure_monitor<'a>(
+        &'a self,
+        _: monitor::Params<'a>,
+    ) -> BackendFuture<'a, DvbResponse<DepartureMonitor>> {
+        self.stall()
+    }
+
+    fn trip_details<'a>(&'a self, _: &'a trip::Params<'_>) -> BackendFuture<'a, DvbResponse<Trip>> {
+        self.stall()
+    }
+
+    fn route_details<'a>(
+        &'a self,
+        _: &'a route::Params<'_>,
+    ) -> BackendFuture<'a, DvbResponse<Routes>> {
+        self.stall()
+    }
+
+    fn lines<'a>(&'a self, _: &'a str) -> BackendFuture<'a, DvbResponse<Lines>> {
+        self.stall()
+    }
+}
+
+async fn call<C: ClientHandler>(
+    client: &Client<C>,
+    tool: &'static str,
+    arguments: Value,
+) -> CallToolResult {
+    let mut params = CallToolRequestParams::new(tool);
+    if let Value::Object(arguments) = arguments {
+        params = params.with_arguments(arguments);
+    }
+    client.call_tool(params).await.expect("tool call succeeds")
+}
+
+fn text_content(result: &CallToolResult) -> &str {
+    &result.content[0].as_text().expect("text content").text
+}
+
+fn json_content(result: &CallToolResult) -> Value {
+    serde_json::from_str(text_content(result)).expect("json content")
+}
+
+fn is_error(result: &CallToolResult) -> bool {
+    result.is_error == Some(true)
+}
+
+async fn read_json(client: &Client, uri: &str) -> Value {
+    let result = client
+        .read_resource(ReadResourceRequestParams::new(uri))
+        .await
+        .expect("resource is readable");
+    match &result.contents[0] {
+        ResourceContents::TextResourceContents { text, .. } => {
+            serde_json::from_str(text).expect("json resource")
+        }
+        other => panic!("unexpected resource contents {other:?}"),
+    }
+}
+
+#[tokio::test]
+async fn sessions_do_not_share_user_context() {
+    let server = DVBServer::default();
+    let (alice, bob) = tokio::join!(connect(server.new_session()), connect(server.new_session()));
+
+    let (_, _) = tokio::join!(
+        call(&alice, "set_origin", json!({ "origin": "Postplatz" })),
+        call(&bob, "set_destination", json!({ "destination": "Bühlau" })),
+    );
+
+    let alice_context = json_content(&call(&alice, "get_user_context", json!({})).await);
+    assert_eq!(alice_context["origin"], "Postplatz");
+    assert_eq!(alice_context["destination"], Value::Null);
+
+    let bob_context = json_content(&call(&bob, "get_user_context", json!({})).await);
+    assert_eq!(bob_context["origin"], Value::Null);
+    assert_eq!(bob_context["destination"], "Bühlau");
+
+    assert!(
+        bob.read_resource(ReadResourceRequestParams::new("dvb://user/origin"))
+            .await
+            .is_err(),
+        "origin of another session must not be visible"
+    );
+    assert!(
+        alice
+            .read_resource(ReadResourceRequestParams::new("dvb://user/origin"))
+            .await
+            .is_ok()
+    );
+
+    call(&alice, "reset_context", json!({})).await;
+    let bob_context = json_content(&call(&bob, "get_user_context", json!({})).await);
+    assert_eq!(bob_context["destination"], "Bühlau");
+
+    alice.cancel().await.unwrap();
+    bob.cancel().await.unwrap();
+}
+
+#[tokio::test]
+async fn find_stations_lists_candidates() {
+    let client = connect(fixture_server()).await;
+
+    let result = call(
+        &client,
+        "find_stations",
+        json!({ "rough_stop_name": "Hauptbahnhof" }),
+    )
+    .await;
+    let found = json_content(&result);
+    assert_eq!(found["Points"].as_array().unwrap().len(), 3);
+    assert_eq!(found["Points"][0]["id"], "33000028");
+
+    let result = call(
+        &client,
+        "find_stations",
+        json!({ "rough_stop_name": "Atlantis" }),
+    )
+    .await;
+    assert!(is_error(&result));
+}
+
+#[tokio::test]
+async fn lookup_stop_id_resolves_unambiguous_names() {
+    let client = connect(fixture_server()).await;
+
+    let result = call(
+        &client,
+        "lookup_stop_id",
+        json!({ "rough_stop_name": "Postplatz" }),
+    )
+    .await;
+    assert_eq!(json_content(&result), json!({ "stop_id": "33000037" }));
+
+    let result = call(
+        &client,
+        "lookup_stop_id",
+        json!({ "rough_stop_name": "Nirgendwo" }),
+    )
+    .await;
+    assert!(is_error(&result));
+    assert!(text_content(&result).contains("no search results"));
+}
+
+#[tokio::test]
+async fn nearby_stations_and_pois() {
+    let client = connect(fixture_server()).await;
+
+    let result = call(
+        &client,
+        "find_nearby_stations",
+        json!({ "rough_stop_name": "Frauenkirche" }),
+    )
+    .await;
+    assert_eq!(json_content(&result)["Points"][1]["name"], "Altmarkt");
+
+    let result = call(&client, "find_pois", json!({ "rough_poi_name": "Zwinger" })).await;
+    assert_eq!(json_content(&result)["Points"][0]["type"], "Poi");
+
+    let result = call(
+        &client,
+        "osm_links_from_query",
+        json!({ "rough_poi_name": "Zwinger" }),
+    )
+    .await;
+    let link = json_content(&result)["results"][0]
+        .as_str()
+        .unwrap()
+        .to_owned();
+    assert!(link.starts_with("https://www.openstreetmap.org/?mlat=51.0"));
+}
+
+#[tokio::test]
+async fn osm_link_and_now() {
+    let client = connect(fixture_server()).await;
+
+    let result = call(
+        &client,
+        "osm_link",
+        json!({ "latitude": 5657516, "longitude": 4621644 }),
+    )
+    .await;
+    assert!(
+        json_content(&result)["link"]
+            .as_str()
+            .unwrap()
+            .contains("mlon=13.7")
+    );
+
+    let result = call(&client, "now", json!({})).await;
+    assert!(chrono::DateTime::parse_from_rfc3339(text_content(&result)).is_ok());
+}
+
+#[tokio::test]
+async fn monitor_departures_from_stop_id() {
+    let client = connect(fixture_server()).await;
+
+    let result = call(
+        &client,
+        "monitor_departures",
+        json!({ "stop_id": "33000037", "mot": ["Tram"], "limit": 10 }),
+    )
+    .await;
+    let board = json_content(&result);
+    assert_eq!(board["Name"], "Postplatz");
+    assert_eq!(board["Departures"][0]["LineName"], "1");
+
+    let result = call(&client, "monitor_departures", json!({ "stop_id": "0" })).await;
+    assert!(is_error(&result));
+}
+
+#[tokio::test]
+async fn monitor_departures_of_one_line_and_direction() {
+    let client = connect(fixture_server()).await;
+
+    let result = call(
+        &client,
+        "monitor_departures",
+        json!({ "stop_id": "33000037", "lines": ["11"], "direction": "bühlau", "limit": 3 }),
+    )
+    .await;
+    let departures = json_content(&result)["Departures"].clone();
+    assert_eq!(departures.as_array().unwrap().len(), 1);
+    assert_eq!(departures[0]["Direction"], "Bühlau");
+}
+
+#[tokio::test]
+async fn monitor_departures_of_any_bus() {
+    let client = connect(fixture_server()).await;
+
+    // VVO is asked for every kind of bus, as the lines tool and route preferences do
+    let result = call(
+        &client,
+        "monitor_departures",
+        json!({ "stop_id": "33000037", "mot": ["Bus"] }),
+    )
+    .await;
+    let departures = json_content(&result)["Departures"].clone();
+    assert_eq!(departures.as_array().unwrap().len(), 1);
+    assert_eq!(departures[0]["Mot"], "CityBus");
+}
+
+#[tokio::test]
+async fn monitor_arrivals_at_a_later_time() {
+    let client = connect(fixture_server()).await;
+
+    let result = call(
+        &client,
+        "monitor_departures",
+        json!({
+            "stop_id": "33000037",
+            "time": "2026-10-16T23:40:00+02:00",
+            "is_arrival": true,
+        }),
+    )
+    .await;
+    let board = json_content(&result);
+    assert_eq!(board["Departures"].as_array().unwrap().len(), 1);
+    assert_eq!(board["Departures"][0]["Direction"], "Leutewitz");
+}
+
+#[tokio::test]
+async fn list_lines_requires_point_id() {
+    let client = connect(fixture_server()).await;
+
+    let result = call(&client, "list_lines", json!({ "point_id": "33000037" })).await;
+    assert_eq!(json_content(&result)["Lines"][1]["Name"], "11");
+
+    let result = call(&client, "list_lines", json!({})).await;
+    assert!(is_error(&result));
+}
+
+#[tokio::test]
+async fn list_lines_filters_by_mode_of_transport() {
+    let client = connect(fixture_server()).await;
+
+    let lines = |mot| {
+        let client = &client;
+        async move {
+            let arguments = json!({ "point_id": "33000037", "mot": mot });
+            let result = call(client, "list_lines", arguments).await;
+            json_content(&result)["Lines"].as_array().unwrap().len()
+        }
+    };
+    assert_eq!(lines(json!(["Tram"])).await, 2);
+    assert_eq!(lines(json!(["Bus", "Ferry"])).await, 0);
+}
+
+#[tokio::test]
+async fn unknown_modes_of_transport_are_rejected() {
+    let client = connect(fixture_server()).await;
+
+    let arguments = json!({ "stop_id": "33000037", "mot": ["Tram", "Bsu"] });
+    let result = call(&client, "monitor_departures", arguments).await;
+    assert!(is_error(&result));
+    assert!(text_content(&result).contains("unknown variant `Bsu`"));
+}
+
+#[tokio::test]
+async fn trip_details_lists_stops() {
+    let client = connect(fixture_server()).await;
+
+    let trip = |target_stop: &str| {
+        json!({
+            "trip_id": "voe:11003: :H:j26",
+            "time": "2026-10-16T14:12:00+02:00",
+            "stop_id": "33000013",
+            "target_stop": target_stop,
+        })
+    };
+    let result = call(&client, "get_trip_details", trip("Hauptbahnhof")).await;
+    let progress = json_content(&result);
+    assert_eq!(result.structured_content.as_ref(), Some(&progress));
+    assert_eq!(progress["now"], "2026-10-16T14:16:30+02:00");
+    assert_eq!(progress["stops"].as_array().unwrap().len(), 6);
+    assert_eq!(progress["stops"][0]["status"], "passed");
+    assert_eq!(progress["stops"][1]["name"], "Carolaplatz");
+    assert_eq!(progress["stops"][1]["status"], "current");
+    assert_eq!(progress["next_stop"]["name"], "Pirnaischer Platz");
+    assert_eq!(progress["delay_minutes"], 1);
+    assert_eq!(
+        progress["target"]["stop"]["expected"],
+        "2026-10-16T14:21:00+02:00"
+    );
+    assert_eq!(progress["target"]["minutes_away"], 4);
+
+    let result = call(&client, "get_trip_details", trip("Postplatz")).await;
+    assert!(is_error(&result));
+}
+
+#[tokio::test]
+async fn route_details_resolve_stop_names() {
+    let client = connect(fixture_server()).await;
+
+    let result = call(
+        &client,
+        "get_route_details",
+        json!({
+            "origin": "Hauptbahnhof",
+            "destination": "Albertplatz",
+            "time": "2026-10-16T14:00:00+02:00",
+            "detail": "legs",
+        }),
+    )
+    .await;
+    let route = &json_content(&result)["routes"][0];
+    assert_eq!(route["duration_minutes"], 14);
+    assert_eq!(route["changes"], 0);
+    assert_eq!(route["walking_minutes"], 2);
+    assert_eq!(route["departure"]["name"], "Hauptbahnhof");
+    assert_eq!(route["departure"]["planned"], "2026-10-16T14:03:00+02:00");
+    assert_eq!(route["departure"]["delay_minutes"], 0);
+    assert_eq!(route["arrival"]["name"], "Albertplatz");
+    let ride = &route["legs"][1];
+    assert_eq!(ride["mode"], "Tram");
+    assert_eq!(ride["line"], "3");
+    assert_eq!(ride["direction"], "Wilder Mann");
+    assert_eq!(ride["from"]["stop_id"], "33000028");
+    assert_eq!(ride["to"]["platform"], "1");
+    assert_eq!(ride["trip_id"], "voe:11003: :H:j26");
+    assert_eq!(ride["stops"][1]["name"], "Pirnaischer Platz");
+    assert_eq!(
+        result.structured_content.as_ref(),
+        Some(&json_content(&result))
+    );
+
+    let result = call(
+        &client,
+        "get_route_details",
+        json!({
+            "origin": "Nirgendwo",
+            "destination": "Albertplatz",
+            "time": "2026-10-16T14:00:00+02:00",
+        }),
+    )
+    .await;
+    assert!(text_content(&result).contains("origin"));
+}
+
+#[tokio::test]
+async fn route_details_publish_their_output_schema() {
+    let client = connect(fixture_server()).await;
+
+    let tools = client.list_all_tools().await.unwrap();
+    let route_tool = tools
+        .iter()
+        .find(|tool| tool.name == "get_route_details")
+        .unwrap();
+    let schema = Value::Object((*route_tool.output_schema.clone().unwrap()).clone());
+    assert_eq!(schema["type"], "object");
+    assert!(schema["properties"]["routes"].is_object());
+    assert!(schema["$defs"]["RouteLeg"]["properties"]["trip_id"].is_object());
+}
+
+#[tokio::test]
+async fn markdown_views() {
+    let client = connect(fixture_server()).await;
+
+    let result = call(
+        &client,
+        "get_route_details",
+        json!({
+            "origin": "Hauptbahnhof",
+            "destination": "Albertplatz",
+            "time": "2026-10-16T14:00:00+02:00",
+            "format": "markdown",
+        }),
+    )
+    .await;
+    let text = text_content(&result);
+    assert!(text.starts_with("**Connection 1**: 14:01 → 14:15 (14 min, 0 changes, 3,00 €)"));
+    assert!(text.contains("1. Walk 2 min\n"));
+    assert!(text.contains(
+        "2. Tram 3 towards Wilder Mann: Hauptbahnhof 14:03, platform 3 → Albertplatz 14:15, platform 1"
+    ));
+
+    let result = call(
+        &client,
+        "monitor_departures",
+        json!({ "stop_id": "33000037", "format": "markdown" }),
+    )
+    .await;
+    assert!(text_content(&result).starts_with("## Postplatz (Dresden)\n"));
+
+    let result = call(
+        &client,
+        "get_trip_details",
+        json!({
+            "trip_id": "voe:11003: :H:j26",
+            "time": "2026-10-16T14:12:00+02:00",
+            "stop_id": "33000013",
+            "format": "markdown",
+        }),
+    )
+    .await;
+    assert!(text_content(&result).contains("- ✓ Albertplatz (14:12) - Departed"));
+
+    let result = call(
+        &client,
+        "monitor_departures",
+        json!({ "stop_id": "33000037", "format": "html" }),
+    )
+    .await;
+    assert!(is_error(&result));
+}
+
+#[tokio::test]
+async fn elicit_tools_reuse_saved_context() {
+    let client = connect(fixture_server()).await;
+
+    call(&client, "set_location", json!({ "location": "Postplatz" })).await;
+    let result = call(&client, "elicit_location", json!({})).await;
+    assert_eq!(text_content(&result), "Currently at Postplatz!");
+
+    // the test client does not support elicitation
+    let result = call(&client, "elicit_destination", json!({})).await;
+    assert!(is_error(&result));
+}
+
+#[tokio::test]
+async fn user_context_resources() {
+    let client = connect(fixture_server()).await;
+
+    let listed = client.list_resources(None).await.unwrap();
+    assert_eq!(listed.resources.len(), 1);
+    assert_eq!(
+        read_json(&client, "dvb://user/context").await["status"],
+        "empty"
+    );
+    assert!(
+        client
+            .read_resource(ReadResourceRequestParams::new("dvb://user/location"))
+            .await
+            .is_err()
+    );
+
+    call(&client, "set_location", json!({ "location": "Postplatz" })).await;
+    call(
+        &client,
+        "set_destination",
+        json!({ "destination": "Albertplatz" }),
+    )
+    .await;
+
+    let listed = client.list_resources(None).await.unwrap();
+    assert_eq!(listed.resources.len(), 3);
+    assert_eq!(
+        read_json(&client, "dvb://user/context").await["status"],
+        "partial"
+    );
+    assert_eq!(
+        read_json(&client, "dvb://user/location").await["location"],
+        "Postplatz"
+    );
+    assert_eq!(
+        read_json(&client, "dvb://user/destination").await["destination"],
+        "Albertplatz"
+    );
+}
+
+#[tokio::test]
+async fn cache_stats_resource() {
+    let uri = "dvb://server/cache";
+    let client = connect(fixture_server()).await;
+    assert!(
+        client
+            .read_resource(ReadResourceRequestParams::new(uri))
+            .await
+            .is_err()
+    );
+
+    let cached = cached_fixtures();
+    let client = connect(DVBServer::new(
+        UserContextStore::default(),
+        Arc::new(cached),
+    ))
+    .await;
+    let listed = client.list_resources(None).await.unwrap();
+    assert!(listed.resources.iter().any(|resource| resource.uri == uri));
+
+    for _ in 0..2 {
+        call(
+            &client,
+            "lookup_stop_id",
+            json!({ "rough_stop_name": "Postplatz" }),
+        )
+        .await;
+    }
+    let stats = read_json(&client, uri).await;
+    assert_eq!(stats, json!({ "hits": 1, "misses": 1, "entries": 1 }));
+}
+
+#[tokio::test]
+async fn departures_resource() {
+    let client = connect(fixture_server()).await;
+
+    let templates = client.list_resource_templates(None).await.unwrap();
+    assert_eq!(
+        templates.resource_templates[0].uri_template,
+        "dvb://departures/{stop_id}{?time,is_arrival,shorttermchanges}"
+    );
+
+    let departures = read_json(&client, "dvb://departures/33000037").await;
+    assert_eq!(departures["stop_id"], "33000037");
+    assert_eq!(
+        departures["departures"]["Departures"][3]["Direction"],
+        "Bühlau"
+    );
+
+    let arrivals = read_json(
+        &client,
+        "dvb://departures/33000037?time=2026-10-16T23:40:00%2B02:00&is_arrival=true",
+    )
+    .await;
+    assert_eq!(arrivals["stop_id"], "33000037");
+    assert_eq!(
+        arrivals["departures"]["Departures"][0]["Direction"],
+        "Leutewitz"
+    );
+
+    assert!(
+        client
+            .read_resource(ReadResourceRequestParams::new("dvb://departures/0"))
+            .await
+            .is_err()
+    );
+    assert!(
+        client
+            .read_resource(ReadResourceRequestParams::new(
+                "dvb://departures/33000037?time=tonight"
+            ))
+            .await
+            .is_err()
+    );
+    assert!(
+        client
+            .read_resource(ReadResourceRequestParams::new("dvb://nothing"))
+            .await
+            .is_err()
+    );
+}
+
+/// Uri and text of the resource embedded in the last message of a prompt
+fn embedded_resource(messages: &[PromptMessage]) -> (&str, Value) {
+    let PromptMessageContent::Resource { resource } = &messages.last().unwrap().content else {
+        panic!("no resource embedded in {messages:?}");
+    };
+    match &resource.resource {
+        ResourceContents::TextResourceContents { uri, text, .. } => {
+            (uri, serde_json::from_str(text).expect("json resource"))
+        }
+        other => panic!("unexpected resource contents {other:?}"),
+    }
+}
+
+fn prompt_arguments(arguments: Value) -> serde_json::Map<String, Value> {
+    arguments.as_object().unwrap().clone()
+}
+
+#[tokio::test]
+async fn prompts_embed_live_data() {
+    let client = connect(fixture_server()).await;
+
+    let prompts = client.list_all_prompts().await.unwrap();
+    let monitor = prompts
+        .iter()
+        .find(|prompt| prompt.name == "departure-monitor")
+        .unwrap();
+    assert_eq!(monitor.arguments.as_ref().unwrap()[0].name, "station");
+
+    let prompt = client
+        .get_prompt(
+            GetPromptRequestParams::new("departure-monitor")
+                .with_arguments(prompt_arguments(json!({ "station": "Postplatz" }))),
+        )
+        .await
+        .unwrap();
+    let (uri, board) = embedded_resource(&prompt.messages);
+    assert_eq!(uri, "dvb://departures/33000037");
+    assert_eq!(board["departures"]["Departures"][3]["Direction"], "Bühlau");
+
+    let prompt = client
+        .get_prompt(
+            GetPromptRequestParams::new("trip-tracker").with_arguments(prompt_arguments(json!({
+                "trip_id": "voe:11003: :H:j26",
+                "stop_id": "33000013",
+                "time": "2026-10-16T14:15:00+02:00",
+            }))),
+        )
+        .await
+        .unwrap();
+    let (uri, trip) = embedded_resource(&prompt.messages);
+    assert_eq!(
+        uri,
+        "dvb://trips/voe:11003:%20:H:j26?stop_id=33000013&time=2026-10-16T14:15:00%2B02:00"
+    );
+    assert_eq!(trip["trip"]["next_stop"]["name"], "Pirnaischer Platz");
+    assert_eq!(read_json(&client, uri).await["trip"], trip["trip"]);
+
+    let prompt = client
+        .get_prompt(
+            GetPromptRequestParams::new("navigation-assistant").with_arguments(prompt_arguments(
+                json!({
+                    "origin": "Hauptbahnhof",
+                    "destination": "Albertplatz",
+                    "time": "2026-10-16T14:00:00+02:00",
+                }),
+            )),
+        )
+        .await
+        .unwrap();
+    let (uri, routes) = embedded_resource(&prompt.messages);
+    assert_eq!(
+        uri,
+        "dvb://routes/33000028/33000013?time=2026-10-16T14:00:00%2B02:00"
+    );
+    assert_eq!(routes["routes"][0]["legs"][1]["line"], "3");
+
+    assert!(
+        client
+            .get_prompt(
+                GetPromptRequestParams::new("trip-tracker")
+                    .with_arguments(prompt_arguments(json!({ "trip_id": "voe:11003: :H:j26" })))
+            )
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn prompts_without_arguments_embed_nothing() {
+    let client = connect(fixture_server()).await;
+
+    for name in ["navigation-assistant", "departure-monitor", "trip-tracker"] {
+        let prompt = client
+            .get_prompt(GetPromptRequestParams::new(name))
+            .await
+            .unwrap();
+        assert!(
+            prompt
+                .messages
+                .iter()
+                .all(|message| matches!(message.content, PromptMessageContent::Text { .. }))
+        );
+    }
+}
+
+#[tokio::test]
+async fn stop_names_and_ids_are_completed() {
+    let client = connect(fixture_server()).await;
+    call(
+        &client,
+        "set_location",
+        json!({ "location": "Hauptbahnhof" }),
+    )
+    .await;
+
+    let names = client
+        .complete_prompt_simple("departure-monitor", "station", "Bahnhof")
+        .await
+        .unwrap();
+    assert_eq!(names, ["Hauptbahnhof", "Bahnhof Neustadt", "Bahnhof Mitte"]);
+
+    let template = "dvb://departures/{stop_id}{?time,is_arrival,shorttermchanges}";
+    let ids = client
+        .complete_resource_simple(template, "stop_id", "Bahnhof")
+        .await
+        .unwrap();
+    assert_eq!(ids, ["33000016", "33000030", "33000028"]);
+
+    let ids = client
+        .complete_prompt_simple("trip-tracker", "stop_id", "Postplatz")
+        .await
+        .unwrap();
+    assert_eq!(ids, ["33000037"]);
+
+    // too short to look up, only saved places match
+    let names = client
+        .complete_prompt_simple("navigation-assistant", "origin", "h")
+        .await
+        .unwrap();
+    assert_eq!(names, ["Hauptbahnhof"]);
+
+    let other = client
+        .complete_resource_simple(template, "time", "2026")
+        .await
+        .unwrap();
+    assert!(other.is_empty());
+}
+
+#[tokio::test]
+async fn completion_waits_for_the_last_keystroke() {
+    let client = Arc::new(connect(fixture_server()).await);
+    let template = "dvb://departures/{stop_id}{?time,is_arrival,shorttermchanges}";
+
+    let overtaken = tokio::spawn({
+        let client = client.clone();
+        async move {
+            client
+                .complete_resource_simple(template, "stop_id", "Bahnhof")
+                .await
+        }
+    });
+    tokio::time::sleep(Duration::from_millis(20)).await;
+    let latest = client
+        .complete_resource_simple(template, "stop_id", "Postplatz")
+        .await
+        .unwrap();
+
+    assert!(overtaken.await.unwrap().unwrap().is_empty());
+    assert_eq!(latest, ["33000037"]);
+}
+
+#[tokio::test]
+async fn upstream_calls_are_reported_as_progress() {
+    let (reports, mut reported) = mpsc::unbounded_channel();
+    let client = connect_with(fixture_server(), Reporting(reports)).await;
+
+    call(
+        &client,
+        "get_route_details",
+        json!({
+            "origin": "Hauptbahnhof",
+            "destination": "Albertplatz",
+            "time": "2026-10-16T14:00:00+02:00",
+        }),
+    )
+    .await;
+
+    let mut messages = Vec::new();
+    while messages.len() < 3 {
+        let message = timeout(Duration::from_secs(5), reported.recv()).await;
+        messages.push(message.unwrap().unwrap());
+    }
+    assert_eq!(
+        messages,
+        [
+            "found stops matching \"Hauptbahnhof\"",
+            "found stops matching \"Albertplatz\"",
+            "fetched routes from 33000028 to 33000013",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn cancelled_tool_calls_drop_upstream_requests() {
+    let dropped = CancellationToken::new();
+    let backend = Stalled {
+        dropped: dropped.clone(),
+    };
+    let client = connect(DVBServer::new(
+        UserContextStore::default(),
+        Arc::new(backend),
+    ))
+    .await;
+
+    let params = CallToolRequestParams::new("find_stations").with_arguments(
+        json!({ "rough_stop_name": "Postplatz" })
+            .as_object()
+            .unwrap()
+            .clone(),
+    );
+    let request = ClientRequest::CallToolRequest(CallToolRequest::new(params));
+    let handle = client
+        .send_cancellable_request(request, PeerRequestOptions::no_options())
+        .await
+        .unwrap();
+    handle.cancel(Some("changed my mind".into())).await.unwrap();
+
+    assert!(
+        timeout(Duration::from_secs(5), dropped.cancelled())
+            .await
+            .is_ok()
+    );
+}
+
+#[tokio::test]
+async fn trips_resource() {
+    let server = fixture_server().with_poll_interval(Duration::from_millis(20));
+    let subscriptions = server.subscriptions.subscriptions().clone();
+    let client = connect(server).await;
+
+    let templates = client.list_resource_templates(None).await.unwrap();
+    assert_eq!(
+        templates.resource_templates[1].uri_template,
+        "dvb://trips/{trip_id}{?stop_id,time}"
+    );
+
+    let uri = "dvb://trips/voe:11003:%20:H:j26?stop_id=33000013";
+    let trip = read_json(&client, uri).await;
+    assert_eq!(trip["trip_id"], "voe:11003: :H:j26");
+    assert_eq!(trip["trip"]["next_stop"]["name"], "Pirnaischer Platz");
+    assert_eq!(trip["trip"]["delay_minutes"], 1);
+
+    client
+        .subscribe(SubscribeRequestParams::new(uri))
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.polled(), [uri]);
+    client
+        .unsubscribe(UnsubscribeRequestParams::new(uri))
+        .await
+        .unwrap();
+    assert!(subscriptions.polled().is_empty());
+
+    for invalid in [
+        "dvb://trips/voe:11003:%20:H:j26",
+        "dvb://trips/voe:11003:%20:H:j26?stop_id=33000013&time=soon",
+    ] {
+        assert!(
+            client
+                .read_resource(ReadResourceRequestParams::new(invalid))
+                .await
+                .is_err()
+        );
+        assert!(
+            client
+                .subscribe(SubscribeRequestParams::new(invalid))
+                .await
+                .is_err()
+        );
+    }
+}
+
+#[tokio::test]
+async fn subscribed_departures_notify_changes() {
+    let server = fixture_server().with_poll_interval(Duration::from_millis(20));
+    let subscriptions = server.subscriptions.subscriptions().clone();
+    let (updates, mut updated) = mpsc::unbounded_channel();
+    let client = connect_with(server, Watching(updates)).await;
+    let uri = "dvb://departures/33000013";
+
+    client
+        .subscribe(SubscribeRequestParams::new(uri))
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.polled(), [uri]);
+
+    // the first poll is the baseline, on the second one line 3 is running late
+    let notified = timeout(Duration::from_secs(5), updated.recv()).await;
+    assert_eq!(notified.unwrap().unwrap(), uri);
+    // later polls see the same board again
+    assert!(
+        timeout(Duration::from_millis(200), updated.recv())
+            .await
+            .is_err()
+    );
+
+    client
+        .unsubscribe(UnsubscribeRequestParams::new(uri))
+        .await
+        .unwrap();
+    assert!(subscriptions.polled().is_empty());
+
+    assert!(
+        client
+            .subscribe(SubscribeRequestParams::new("dvb://user/context"))
+            .await
+            .is_err()
+    );
+    assert!(
+        client
+            .subscribe(SubscribeRequestParams::new(
+                "dvb://departures/33000013?time=tonight"
+            ))
+            .await
+            .is_err()
+    );
+}
+
+#[tokio::test]
+async fn polling_stops_when_the_session_ends() {
+    let server = fixture_server().with_poll_interval(Duration::from_millis(20));
+    let subscriptions = server.subscriptions.subscriptions().clone();
+    let client = connect(server).await;
+
+    client
+        .subscribe(SubscribeRequestParams::new("dvb://departures/33000037"))
+        .await
+        .unwrap();
+    assert_eq!(subscriptions.polled().len(), 1);
+
+    client.cancel().await.unwrap();
+    let stopped = timeout(Duration::from_secs(5), async {
+        while !subscriptions.polled().is_empty() {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+    });
+    assert!(stopped.await.is_ok());
+}
+
+#[tokio::test]
+async fn ambiguous_stop_without_elicitation_lists_candidates() {
+    let client = connect(fixture_server()).await;
+
+    let result = call(
+        &client,
+        "lookup_stop_id",
+        json!({ "rough_stop_name": "Bahnhof" }),
+    )
+    .await;
+    assert!(!is_error(&result));
+    let ambiguous = json_content(&result);
+    assert_eq!(ambiguous["status"], "ambiguous");
+    assert_eq!(ambiguous["field"], "rough_stop_name");
+    let candidates = ambiguous["candidates"].as_array().unwrap();
+    assert_eq!(candidates.len(), 3);
+    assert_eq!(candidates[1]["stop_id"], "33000030");
+
+    let result = call(
+        &client,
+        "get_route_details",
+        json!({
+            "origin": "Bahnhof",
+            "destination": "Albertplatz",
+            "time": "2026-10-16T14:00:00+02:00",
+        }),
+    )
+    .await;
+    assert_eq!(json_content(&result)["field"], "origin");
+}
+
+#[tokio::test]
+async fn ambiguous_stop_is_elicited() {
+    let client = connect_with(
+        fixture_server(),
+        Answering::new(Some(json!({ "stop_id": "33000028" }))),
+    )
+    .await;
+
+    let result = call(
+        &client,
+        "lookup_stop_id",
+        json!({ "rough_stop_name": "Bahnhof" }),
+    )
+    .await;
+    assert_eq!(json_content(&result), json!({ "stop_id": "33000028" }));
+
+    let asked = client.service().asked.lock().unwrap().clone();
+    assert_eq!(asked.len(), 1);
+    let choices = &asked[0]["properties"]["stop_id"]["oneOf"];
+    assert_eq!(choices[0]["const"], "33000016");
+    assert_eq!(choices[0]["title"], "Bahnhof Neustadt");
+
+    // the chosen stop is used for routing
+    let result = call(
+        &client,
+        "get_route_details",
+        json!({
+            "origin": "Bahnhof",
+            "destination": "Albertplatz",
+            "time": "2026-10-16T14:00:00+02:00",
+        }),
+    )
+    .await;
+    assert_eq!(json_content(&result)["routes"][0]["duration_minutes"], 14);
+}
+
+#[tokio::test]
+async fn declined_stop_choice_is_ambiguous() {
+    let client = connect_with(fixture_server(), Answering::new(None)).await;
+
+    let result = call(
+        &client,
+        "lookup_stop_id",
+        json!({ "rough_stop_name": "Bahnhof" }),
+    )
+    .await;
+    assert_eq!(json_content(&result)["status"], "ambiguous");
+
+    // unambiguous names never ask
+    call(
+        &client,
+        "lookup_stop_id",
+        json!({ "rough_stop_name": "Postplatz" }),
+    )
+    .await;
+    assert_eq!(client.service().asked.lock().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn route_endpoints_default_to_saved_context() {
+    let client = connect(fixture_server()).await;
+    let at_two = json!({ "time": "2026-10-16T14:00:00+02:00" });
+
+    let result = call(&client, "get_route_details", at_two.clone()).await;
+    assert!(is_error(&result));
+    assert!(text_content(&result).contains("no origin"));
+
+    call(&client, "set_origin", json!({ "origin": "Hauptbahnhof" })).await;
+    call(
+        &client,
+        "set_destination",
+        json!({ "destination": "Albertplatz" }),
+    )
+    .await;
+    let routes = json_content(&call(&client, "get_route_details", at_two.clone()).await);
+    assert_eq!(routes["routes"][0]["duration_minutes"], 14);
+    assert_eq!(
+        routes["from_context"],
+        json!([
+            { "field": "origin", "source": "origin", "value": "Hauptbahnhof" },
+            { "field": "destination", "source": "destination", "value": "Albertplatz" },
+        ])
+    );
+
+    // the current location beats the journey origin
+    call(&client, "set_location", json!({ "location": "Bahnhof" })).await;
+    let result = call(&client, "get_route_details", at_two).await;
+    assert_eq!(json_content(&result)["query"], "Bahnhof");
+
+    // explicit arguments are not reported
+    let routes = json_content(
+        &call(
+            &client,
+            "get_route_details",
+            json!({
+                "origin": "Hauptbahnhof",
+                "time": "2026-10-16T14:00:00+02:00",
+            }),
+        )
+        .await,
+    );
+    assert_eq!(routes["from_context"][0]["field"], "destination");
+    assert_eq!(routes["from_context"].as_array().unwrap().len(), 1);
+}
+
+#[tokio::test]
+async fn routes_are_planned_with_saved_preferences() {
+    let client = connect(fixture_server()).await;
+    let request = json!({
+        "origin": "Hauptbahnhof",
+        "destination": "Albertplatz",
+        "time": "2026-10-16T14:00:00+02:00",
+    });
+
+    let result = call(
+        &client,
+        "set_preferences",
+        json!({ "mobility": { "restriction": "High" } }),
+    )
+    .await;
+    assert_eq!(json_content(&result)["mobility"]["restriction"], "High");
+    let routes = json_content(&call(&client, "get_route_details", request.clone()).await);
+    assert_eq!(routes["routes"][0]["duration_minutes"], 19);
+
+    // arguments of the request win over the saved ones
+    let mut unrestricted = request.clone();
+    unrestricted["mobility"] = json!({ "restriction": "None" });
+    let routes = json_content(&call(&client, "get_route_details", unrestricted).await);
+    assert_eq!(routes["routes"][0]["duration_minutes"], 14);
+
+    call(&client, "reset_context", json!({})).await;
+    let context = json_content(&call(&client, "get_user_context", json!({})).await);
+    assert_eq!(context["mobility"], Value::Null);
+    let routes = json_content(&call(&client, "get_route_details", request).await);
+    assert_eq!(routes["routes"][0]["duration_minutes"], 14);
+}
+
+#[tokio::test]
+async fn departures_are_planned_back_from_the_arrival() {
+    let client = connect(fixture_server()).await;
+    call(
+        &client,
+        "set_location",
+        json!({ "location": "Hauptbahnhof" }),
+    )
+    .await;
+
+    // connections arrive at 14:35, 14:40 (cancelled), 14:45 and 14:55
+    let result = call(
+        &client,
+        "plan_departure",
+        json!({
+            "destination": "Albertplatz",
+            "arrive_by": "2026-10-16T14:50:00+02:00",
+        }),
+    )
+    .await;
+    let plan = json_content(&result);
+    assert_eq!(plan["buffer_minutes"], 5);
+    assert_eq!(plan["connection"]["leave_at"], "2026-10-16T14:31:00+02:00");
+    assert_eq!(plan["connection"]["leave_in_minutes"], 14);
+    assert_eq!(plan["connection"]["arrive_at"], "2026-10-16T14:45:00+02:00");
+    assert_eq!(plan["connection"]["spare_minutes"], 5);
+    assert_eq!(plan["connection"]["route"]["legs"][1]["line"], "3");
+    assert_eq!(plan["fallback"]["leave_at"], "2026-10-16T14:21:00+02:00");
+    assert_eq!(plan["fallback"]["leave_in_minutes"], 4);
+    assert_eq!(
+        plan["from_context"],
+        json!([{ "field": "origin", "source": "location", "value": "Hauptbahnhof" }])
+    );
+    assert_eq!(result.structured_content, Some(plan));
+
+    // without a buffer the 14:55 arrival is just in time, with the 14:45 one as fallback
+    let client = connect(fixture_server()).await;
+    let result = call(
+        &client,
+        "plan_departure",
+        json!({
+            "origin": "Hauptbahnhof",
+            "destination": "Albertplatz",
+            "arrive_by": "2026-10-16T14:55:00+02:00",
+            "buffer_minutes": 0,
+        }),
+    )
+    .await;
+    let plan = json_content(&result);
+    assert_eq!(plan["connection"]["arrive_at"], "2026-10-16T14:55:00+02:00");
+    assert_eq!(plan["connection"]["spare_minutes"], 0);
+    assert_eq!(plan["fallback"]["arrive_at"], "2026-10-16T14:45:00+02:00");
+
+    // connections that have already left are no fallback
+    let now = DateTime::parse_from_rfc3339("2026-10-16T14:25:00+02:00").unwrap();
+    let client = connect(fixture_server().with_clock(Clock::fixed(now))).await;
+    let arrive_by = |time: &str| {
+        json!({
+            "origin": "Hauptbahnhof",
+            "destination": "Albertplatz",
+            "arrive_by": time,
+        })
+    };
+    let result = call(
+        &client,
+        "plan_departure",
+        arrive_by("2026-10-16T14:50:00+02:00"),
+    )
+    .await;
+    let plan = json_content(&result);
+    assert_eq!(plan["connection"]["leave_in_minutes"], 6);
+    assert_eq!(plan["fallback"], Value::Null);
+
+    let result = call(
+        &client,
+        "plan_departure",
+        arrive_by("2026-10-16T14:35:00+02:00"),
+    )
+    .await;
+    assert!(is_error(&result));
+    assert!(text_content(&result).contains("arriving by 14:30"));
+}
+
+#[tokio::test]
+async fn gps_positions_are_taken_as_vvo_points() {
+    let client = connect(fixture_server()).await;
+    // in front of Dresden Hauptbahnhof
+    let position = json!({ "latitude": 51.0405, "longitude": 13.732 });
+
+    let result = call(
+        &client,
+        "find_nearby_stations",
+        json!({ "coords": position }),
+    )
+    .await;
+    assert_eq!(json_content(&result)["Points"][0]["name"], "Hauptbahnhof");
+
+    let result = call(
+        &client,
+        "get_route_details",
+        json!({
+            "origin_coords": position,
+            "destination": "Albertplatz",
+            "time": "2026-10-16T14:00:00+02:00",
+        }),
+    )
+    .await;
+    assert_eq!(json_content(&result)["routes"][0]["duration_minutes"], 14);
+
+    // a saved position is the origin of later routes
+    let result = call(&client, "set_location", json!({ "coords": position })).await;
+    assert!(text_content(&result).contains("coord:4621644:5657572"));
+    let result = call(
+        &client,
+        "get_route_details",
+        json!({
+            "destination": "Albertplatz",
+            "time": "2026-10-16T14:00:00+02:00",
+        }),
+    )
+    .await;
+    assert_eq!(json_content(&result)["routes"][0]["duration_minutes"], 14);
+
+    let result = call(
+        &client,
+        "get_route_details",
+        json!({
+            "origin": "Hauptbahnhof",
+            "origin_coords": position,
+            "destination": "Albertplatz",
+            "time": "2026-10-16T14:00:00+02:00",
+        }),
+    )
+    .await;
+    assert!(is_error(&result));
+    assert!(text_content(&result).contains("either origin or origin_coords, not both"));
+
+    let result = call(
+        &client,
+        "find_nearby_stations",
+        json!({ "coords": { "latitude": 151.0, "longitude": 13.7 } }),
+    )
+    .await;
+    assert!(is_error(&result));
+    assert!(text_content(&result).contains("invalid coordinates for coords"));
+}
+
+#[tokio::test]
+async fn via_stops_are_resolved_and_planned_in_sections() {
+    let client = connect(fixture_server()).await;
+    let route = |via: Value| {
+        json!({
+            "origin": "Hauptbahnhof",
+            "destination": "Albertplatz",
+            "time": "2026-10-16T14:00:00+02:00",
+            "via": via,
+            "detail": "legs",
+        })
+    };
+
+    // a single via stop is left to the VVO route planner
+    let result = call(
+        &client,
+        "get_route_details",
+        route(json!([{ "stop": "Postplatz" }])),
+    )
+    .await;
+    assert_eq!(json_content(&result)["routes"][0]["duration_minutes"], 14);
+
+    // as the tool took it before via stops could have dwell times
+    let legacy = call(&client, "get_route_details", route(json!("Postplatz"))).await;
+    assert_eq!(json_content(&legacy), json_content(&result));
+
+    // dwell times need one query per section, continuing after the first arrival
+    let result = call(
+        &client,
+        "get_route_details",
+        route(json!([{ "stop": "33000037", "dwell_minutes": 10 }])),
+    )
+    .await;
+    let sections = json_content(&result)["sections"].clone();
+    assert_eq!(sections.as_array().unwrap().len(), 2);
+    assert_eq!(sections[0]["from"], "Hauptbahnhof");
+    assert_eq!(sections[0]["routes"][0]["legs"][0]["line"], "7");
+    assert_eq!(sections[1]["from"], "33000037");
+    assert_eq!(sections[1]["dwell_minutes"], 10);
+    assert_eq!(sections[1]["time"], "2026-10-16T14:21:00+02:00");
+    assert_eq!(sections[1]["routes"][0]["legs"][0]["line"], "4");
+
+    let result = call(
+        &client,
+        "get_route_details",
+        route(json!([{ "stop": "Postplatz" }, { "stop": "Nirgendwo" }])),
+    )
+    .await;
+    assert!(is_error(&result));
+    assert!(text_content(&result).contains(r#"via[1] "Nirgendwo""#));
+}
+
+#[tokio::test]
+async fn monitor_departures_from_stop_name() {
+    let client = connect(fixture_server()).await;
+
+    let result = call(
+        &client,
+        "monitor_departures",
+        json!({ "stop_name": "Postplatz" }),
+    )
+    .await;
+    assert_eq!(json_content(&result)["Name"], "Postplatz");
+
+    let result = call(
+        &client,
+        "monitor_departures",
+        json!({ "stop_name": "Bahnhof" }),
+    )
+    .await;
+    assert_eq!(json_content(&result)["field"], "stop_name");
+
+    let result = call(&client, "monitor_departures", json!({})).await;
+    assert!(is_error(&result));
+}
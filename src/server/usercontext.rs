@@ -1,5 +1,17 @@
 use rmcp::schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
+use std::{
+    collections::HashMap,
+    sync::{
+        Arc, Mutex, MutexGuard,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use crate::server::{
+    args::{MobilityOptions, RoutePreferences},
+    context_file::ContextFile,
+};
 
 /// Status of user context completeness
 #[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
@@ -22,6 +34,10 @@ pub struct UserContext {
     pub location: Option<String>,
     /// Where the user wants to go
     pub destination: Option<String>,
+    /// Accessibility needs routes are planned with
+    pub mobility: Option<MobilityOptions>,
+    /// Route preferences routes are planned with
+    pub preferences: Option<RoutePreferences>,
     /// Last time context was updated
     pub last_updated: String,
     /// Whether any context is available
@@ -91,6 +107,8 @@ impl UserContext {
             origin,
             location,
             destination,
+            mobility: None,
+            preferences: None,
             last_updated: chrono::Local::now().to_rfc3339(),
             context_available: any_set,
             status,
@@ -98,3 +116,182 @@ impl UserContext {
         }
     }
 }
+
+impl From<SavedContext> for UserContext {
+    fn from(
+        SavedContext {
+            origin,
+            location,
+            destination,
+            mobility,
+            preferences,
+        }: SavedContext,
+    ) -> Self {
+        Self {
+            mobility,
+            preferences,
+            ..Self::new(origin, location, destination)
+        }
+    }
+}
+
+/// Context values saved for a single session
+///
+/// Besides the places this holds the mobility needs and route preferences
+/// routes are planned with when a request leaves them out.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct SavedContext {
+    pub origin: Option<String>,
+    pub location: Option<String>,
+    pub destination: Option<String>,
+    pub mobility: Option<MobilityOptions>,
+    pub preferences: Option<RoutePreferences>,
+}
+
+impl SavedContext {
+    /// Start of a route: where the user currently is, otherwise their journey origin
+    pub fn route_origin(&self) -> Option<ContextValue> {
+        let (source, value) = match (&self.location, &self.origin) {
+            (Some(location), _) => ("location", location),
+            (None, Some(origin)) => ("origin", origin),
+            (None, None) => return None,
+        };
+        Some(ContextValue {
+            field: "origin",
+            source,
+            value: value.clone(),
+        })
+    }
+
+    /// End of a route: the saved destination
+    pub fn route_destination(&self) -> Option<ContextValue> {
+        Some(ContextValue {
+            field: "destination",
+            source: "destination",
+            value: self.destination.clone()?,
+        })
+    }
+}
+
+/// A request argument that was filled in from the saved context
+#[derive(Debug, Clone, PartialEq, Serialize, JsonSchema)]
+pub struct ContextValue {
+    /// The argument that was missing from the request
+    pub field: &'static str,
+    /// The saved context entry used in its place
+    pub source: &'static str,
+    pub value: String,
+}
+
+/// Identifies one MCP session within a [`UserContextStore`]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SessionKey(u64);
+
+/// Saved user context of all sessions served by one server, keyed by session
+///
+/// With a [`ContextFile`] attached, new sessions start from the persisted context
+/// and every update is written back to disk.
+#[derive(Debug, Clone, Default)]
+pub struct UserContextStore {
+    next_key: Arc<AtomicU64>,
+    sessions: Arc<Mutex<HashMap<SessionKey, SavedContext>>>,
+    persisted: Option<Arc<Persisted>>,
+}
+
+#[derive(Debug)]
+struct Persisted {
+    file: ContextFile,
+    latest: Mutex<SavedContext>,
+}
+
+impl UserContextStore {
+    /// Store that loads from and saves to `file`
+    ///
+    /// All sessions share the one file, so this is only meant for a single client over stdio.
+    pub fn persistent(file: ContextFile) -> Self {
+        let latest = Mutex::new(file.load());
+        Self {
+            persisted: Some(Arc::new(Persisted { file, latest })),
+            ..Default::default()
+        }
+    }
+
+    /// Register a new session, starting with the persisted context if there is one
+    pub fn open_session(&self) -> Session {
+        let key = SessionKey(self.next_key.fetch_add(1, Ordering::Relaxed));
+        let initial = self
+            .persisted
+            .as_ref()
+            .map(|persisted| lock(&persisted.latest).clone())
+            .unwrap_or_default();
+        self.sessions().insert(key, initial);
+        Session {
+            key,
+            store: self.clone(),
+        }
+    }
+
+    pub fn get(&self, key: SessionKey) -> SavedContext {
+        self.sessions().get(&key).cloned().unwrap_or_default()
+    }
+
+    pub fn update<R>(&self, key: SessionKey, f: impl FnOnce(&mut SavedContext) -> R) -> R {
+        let (result, updated) = {
+            let mut sessions = self.sessions();
+            let saved = sessions.entry(key).or_default();
+            (f(saved), saved.clone())
+        };
+
+        if let Some(persisted) = &self.persisted {
+            if let Err(error) = persisted.file.save(&updated) {
+                tracing::warn!(
+                    "failed to save context to {}: {error}",
+                    persisted.file.path().display()
+                );
+            }
+            *lock(&persisted.latest) = updated;
+        }
+        result
+    }
+
+    fn close(&self, key: SessionKey) {
+        self.sessions().remove(&key);
+    }
+
+    fn sessions(&self) -> MutexGuard<'_, HashMap<SessionKey, SavedContext>> {
+        lock(&self.sessions)
+    }
+}
+
+// the guarded values stay consistent even if a holder panicked
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// A session's handle into the [`UserContextStore`], its context is dropped along with it
+#[derive(Debug)]
+pub struct Session {
+    key: SessionKey,
+    store: UserContextStore,
+}
+
+impl Session {
+    pub fn context(&self) -> SavedContext {
+        self.store.get(self.key)
+    }
+
+    pub fn update_context<R>(&self, f: impl FnOnce(&mut SavedContext) -> R) -> R {
+        self.store.update(self.key, f)
+    }
+
+    pub fn store(&self) -> &UserContextStore {
+        &self.store
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.store.close(self.key);
+    }
+}